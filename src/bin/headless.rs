@@ -0,0 +1,206 @@
+extern crate rip8;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::str::FromStr;
+use rip8::{Cpu, Quirks, Rom};
+use rip8::core::cpu::INSTRUCTIONS_PER_FRAME;
+use rip8::core::dump;
+use rip8::core::headless::{Headless, KeyPress, Outcome, Stop};
+use rip8::core::movie::Movie;
+use rip8::core::quirks::PRESETS;
+use rip8::core::trace::{Filter, Trace};
+
+const USAGE: &str = "usage: rip8-headless <rom> [--frames <n>] [--until-pc <addr>] [--until-opcode <opcode>] \
+                     [--key <frame>:<key>[:<frames>]]... [--quirks <preset>] [--quirk <name>=on|off]... \
+                     [--ipf <n>] [--seed <n>] [--movie <file>] [--output ascii|png|hash] [-o <file>] \
+                     [--trace <file> [--trace-pc <start>-<end>]... [--trace-only <mnemonic>,...]]";
+const DEFAULT_FRAMES: u64 = 600; // 10 seconds, or the length of the movie
+
+// Exit codes, 1 is an emulator error
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_STOPPED: i32 = 3; // a stop condition was given but never hit
+
+enum Output {
+    Ascii,
+    Png,
+    Hash
+}
+
+struct Args {
+    rom_path: String,
+    frames: Option<u64>,
+    stops: Vec<Stop>,
+    keys: Vec<KeyPress>,
+    quirks: Quirks,
+    ipf: usize,
+    seed: Option<u64>,
+    movie: Option<String>,
+    output: Output,
+    out_path: Option<String>,
+    trace: Option<String>,
+    trace_filter: Filter
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1);
+    let mut parsed = Args {
+        rom_path: String::new(),
+        frames: None,
+        stops: Vec::new(),
+        keys: Vec::new(),
+        quirks: Quirks::default(),
+        ipf: INSTRUCTIONS_PER_FRAME,
+        seed: None,
+        movie: None,
+        output: Output::Ascii,
+        out_path: None,
+        trace: None,
+        trace_filter: Filter::default()
+    };
+    let mut overrides = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => parsed.frames = Some(number(&arg, args.next())?),
+            "--until-pc" => parsed.stops.push(Stop::Pc(hex(&arg, args.next())?)),
+            "--until-opcode" => parsed.stops.push(Stop::Opcode(hex(&arg, args.next())?)),
+            "--key" => parsed.keys.push(KeyPress::parse(&args.next().ok_or("--key needs <frame>:<key>")?)?),
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset")?;
+                parsed.quirks = Quirks::preset(&name)
+                    .ok_or(format!("unknown preset {}, expected one of {}", name, PRESETS.join(", ")))?;
+            },
+            "--quirk" => overrides.push(args.next().ok_or("--quirk needs <name>=on|off")?),
+            "--ipf" => parsed.ipf = number(&arg, args.next())?,
+            "--seed" => parsed.seed = Some(number(&arg, args.next())?),
+            "--movie" => parsed.movie = Some(args.next().ok_or("--movie needs a file")?),
+            "--output" => parsed.output = match args.next().as_deref() {
+                Some("ascii") => Output::Ascii,
+                Some("png") => Output::Png,
+                Some("hash") => Output::Hash,
+                _ => return Err("--output needs one of ascii, png, hash".to_string())
+            },
+            "--trace" => parsed.trace = Some(args.next().ok_or("--trace needs a file")?),
+            "--trace-pc" => parsed.trace_filter.ranges.push(Filter::parse_range(&args.next().ok_or("--trace-pc needs <start>-<end>")?)?),
+            "--trace-only" => parsed.trace_filter.mnemonics.extend(Filter::parse_mnemonics(&args.next().ok_or("--trace-only needs mnemonics like drw,call")?)),
+            "-o" => parsed.out_path = Some(args.next().ok_or("-o needs a file")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => parsed.rom_path = arg
+        }
+    }
+    for arg in overrides {
+        parsed.quirks.apply_override(&arg)?;
+    }
+    if parsed.rom_path.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(parsed)
+}
+
+fn number<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a number", option))?;
+    value.parse().map_err(|_| format!("invalid number {} for {}", value, option))
+}
+
+// Addresses and opcodes are hex, with or without 0x in front
+fn hex(option: &str, value: Option<String>) -> Result<u16, String> {
+    let value = value.ok_or(format!("{} needs a hex number", option))?;
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid hex number {} for {}", value, option))
+}
+
+fn write_output(args: &Args, cpu: &Cpu) -> io::Result<()> {
+    let data = match args.output {
+        Output::Ascii => dump::ascii(cpu.screen()).into_bytes(),
+        Output::Png => dump::png(cpu.screen()),
+        Output::Hash => format!("{:016x}\n", dump::hash(cpu.screen())).into_bytes()
+    };
+    match args.out_path {
+        Some(ref path) => File::create(path)?.write_all(&data),
+        None => io::stdout().write_all(&data)
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    let rom = match Rom::new(args.rom_path.clone()) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let movie = args.movie.as_ref().map(|path| {
+        match fs::read(path).map_err(|err| err.to_string())
+            .and_then(|bytes| Movie::from_bytes(&bytes, &rom.rom).map_err(|err| err.to_string())) {
+            Ok(movie) => movie,
+            Err(err) => {
+                eprintln!("Unable to load movie from {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    });
+    let frames = args.frames.unwrap_or_else(|| movie.as_ref().map_or(DEFAULT_FRAMES, |movie| movie.frames.len() as u64));
+    let mut cpu = Cpu::new(rom, args.quirks, false, false);
+    cpu.set_instructions_per_frame(args.ipf);
+    if let Some(seed) = args.seed {
+        cpu.reseed(seed);
+    }
+    cpu.load_font();
+    if let Err(err) = cpu.load_rom() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    if let Some(movie) = movie {
+        if let Err(err) = cpu.play_movie(movie) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(ref path) = args.trace {
+        match File::create(path) {
+            Ok(file) => cpu.set_trace(Trace::new(Box::new(BufWriter::new(file)), args.trace_filter.clone())),
+            Err(err) => {
+                eprintln!("Unable to write trace to {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut headless = Headless::new(args.keys.clone());
+    let mut exit_code = 0;
+    match headless.run(&mut cpu, frames, &args.stops) {
+        Ok(Outcome::Frames) if !args.stops.is_empty() => {
+            eprintln!("No stop condition hit within {} frames", frames);
+            exit_code = EXIT_NOT_STOPPED;
+        },
+        Ok(_) => {},
+        Err(err) => {
+            eprintln!("CPU stopped: {}", err);
+            eprintln!("Register dump: {:#?}", cpu.registers());
+            exit_code = 1;
+        }
+    }
+
+    if let Some(trace) = cpu.take_trace() {
+        if let Err(err) = trace.finish() {
+            eprintln!("Unable to write trace: {}", err);
+            exit_code = 1;
+        }
+    }
+    if let Err(err) = write_output(&args, &cpu) {
+        eprintln!("Unable to write output: {}", err);
+        exit_code = 1;
+    }
+    process::exit(exit_code);
+}
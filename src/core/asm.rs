@@ -0,0 +1,737 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::instruction::Instruction;
+use super::instructions::encode;
+
+// Includes and macros nested deeper than this are most likely including or calling themselves
+const MAX_DEPTH: usize = 16;
+
+// Where something went wrong, line numbers start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>, // labels and their addresses
+    pub lines: Vec<(u16, String, usize)> // where the code for each source line starts
+}
+
+impl Assembly {
+    // One "0x0200 name" line per label and one "0x0200 file:line" per line of source, by address
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(u16, String)> = self.symbols.iter()
+            .map(|(name, address)| (*address, name.clone()))
+            .chain(self.lines.iter().map(|&(address, ref file, line)| (address, format!("{}:{}", file, line))))
+            .collect();
+        symbols.sort();
+        symbols.iter().map(|&(address, ref name)| format!("0x{:04x} {}\n", address, name)).collect()
+    }
+}
+
+// A symbol file read back in, for debuggers
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Symbols {
+    pub labels: Vec<(u16, String)>,
+    pub lines: Vec<(u16, String, usize)>
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::default();
+        for line in text.lines() {
+            let (address, name) = split_first(line.trim());
+            let address = match u16::from_str_radix(address.trim_start_matches("0x"), 16) {
+                Ok(address) => address,
+                Err(_) => continue
+            };
+            match name.rfind(':').map(|at| (&name[..at], name[at + 1..].parse())) {
+                Some((file, Ok(number))) => symbols.lines.push((address, file.to_string(), number)),
+                _ if !name.is_empty() => symbols.labels.push((address, name.to_string())),
+                _ => {}
+            }
+        }
+        symbols
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|label| label.1 == name).map(|label| label.0)
+    }
+
+    // The closest label at or before the address
+    pub fn label_for(&self, address: u16) -> Option<&str> {
+        self.labels.iter().filter(|label| label.0 <= address).max_by_key(|label| label.0).map(|label| label.1.as_str())
+    }
+
+    pub fn line_for(&self, address: u16) -> Option<(&str, usize)> {
+        self.lines.iter().find(|line| line.0 == address).map(|line| (line.1.as_str(), line.2))
+    }
+
+    // Paths match when one ends with the other, editors tend to hand out absolute ones
+    pub fn address_for(&self, file: &str, number: usize) -> Option<u16> {
+        self.lines.iter()
+            .find(|line| line.2 == number && (Path::new(file).ends_with(&line.1) || Path::new(&line.1).ends_with(file)))
+            .map(|line| line.0)
+    }
+}
+
+// One line of source once includes and macros are expanded. Lines coming out of a macro keep the
+// position of the line that used it.
+#[derive(Debug, Clone)]
+struct Line {
+    file: String,
+    number: usize,
+    order: usize, // its place in the source, included files count where they are included
+    text: String
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    Range(u8, u8), // Vx-Vy
+    I,
+    IndirectI,     // [I]
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Hf,
+    R,
+    Pitch,
+    Long(String),  // long <expr>, only after ld I
+    Value(String)  // anything else is an expression
+}
+
+impl Operand {
+    fn parse(text: &str) -> Operand {
+        let lower = text.to_lowercase();
+        match lower.as_str() {
+            "i" => return Operand::I,
+            "[i]" => return Operand::IndirectI,
+            "dt" => return Operand::Dt,
+            "st" => return Operand::St,
+            "k" => return Operand::K,
+            "f" => return Operand::F,
+            "b" => return Operand::B,
+            "hf" => return Operand::Hf,
+            "r" => return Operand::R,
+            "pitch" => return Operand::Pitch,
+            _ => {}
+        }
+        if let Some(expr) = lower.strip_prefix("long ") {
+            return Operand::Long(text[text.len() - expr.len()..].trim().to_string());
+        }
+        if let Some(x) = register(&lower) {
+            return Operand::V(x);
+        }
+        if let Some(at) = lower.find('-') {
+            if let (Some(x), Some(y)) = (register(lower[..at].trim()), register(lower[at + 1..].trim())) {
+                return Operand::Range(x, y);
+            }
+        }
+        Operand::Value(text.to_string())
+    }
+}
+
+fn register(word: &str) -> Option<u8> {
+    if word.len() == 2 && word.starts_with('v') {
+        u8::from_str_radix(&word[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+enum Statement {
+    Label(String),
+    Constant(String, String),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Instruction(String, Vec<Operand>)
+}
+
+// Reads the same mnemonics the debugger prints. Lines are
+//
+//     label: mnemonic operand, operand ; comment
+//     name = expression
+//     db 1, #ff, 0b1010, label + 2
+//     dw #1234
+//     include "other.8s"
+//     macro name param, param ... endm
+//
+// Numbers are decimal, hex with # or 0x, or binary with 0b. Labels and constants can be used before
+// they are defined, since everything is laid out before any code is generated.
+pub struct Assembler {
+    origin: u16,
+    macros: HashMap<String, Macro>,
+    recording: Option<(String, Macro)>, // the macro being defined
+    lines: Vec<Line>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, String>,
+    read: usize, // source lines read so far
+    errors: Vec<(usize, AsmError)> // with the order of the line they are about
+}
+
+impl Assembler {
+    pub fn new(origin: u16) -> Assembler {
+        Assembler {
+            origin,
+            macros: HashMap::new(),
+            recording: None,
+            lines: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            read: 0,
+            errors: Vec::new()
+        }
+    }
+
+    // Includes are looked up next to the file including them
+    pub fn assemble_file(mut self, path: &Path) -> Result<Assembly, Vec<AsmError>> {
+        let name = path.display().to_string();
+        match fs::read_to_string(path) {
+            Ok(source) => self.read(&name, &source, 0),
+            Err(err) => self.error(&name, 0, format!("unable to read {}: {}", name, err))
+        }
+        self.finish()
+    }
+
+    // Includes are looked up relative to the working directory
+    pub fn assemble(mut self, name: &str, source: &str) -> Result<Assembly, Vec<AsmError>> {
+        self.read(name, source, 0);
+        self.finish()
+    }
+
+    // While reading, about the line read last
+    fn error(&mut self, file: &str, line: usize, message: String) {
+        self.errors.push((self.read, AsmError { file: file.to_string(), line, message }));
+    }
+
+    fn line_error(&mut self, line: &Line, message: String) {
+        self.errors.push((line.order, AsmError { file: line.file.clone(), line: line.number, message }));
+    }
+
+    fn read(&mut self, file: &str, source: &str, depth: usize) {
+        for (number, text) in source.lines().enumerate() {
+            self.read += 1;
+            self.statement(file, number + 1, strip_comment(text).trim(), depth);
+        }
+        if let Some((name, _)) = self.recording.take() {
+            self.error(file, source.lines().count(), format!("macro {} is missing its endm", name));
+        }
+    }
+
+    // First pass, expands includes and macros into self.lines
+    fn statement(&mut self, file: &str, number: usize, text: &str, depth: usize) {
+        let first = text.split_whitespace().next().unwrap_or("");
+        if let Some((name, mut recorded)) = self.recording.take() {
+            match first {
+                "endm" => {
+                    self.macros.insert(name, recorded);
+                },
+                "macro" => self.error(file, number, "macros can not be defined inside macros".to_string()),
+                _ => {
+                    recorded.body.push(text.to_string());
+                    self.recording = Some((name, recorded));
+                }
+            }
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        // labels go first, so a macro or include can follow them on the same line
+        let mut text = text;
+        while let Some(at) = text.find(':') {
+            if !is_identifier(&text[..at]) {
+                break;
+            }
+            self.lines.push(Line { file: file.to_string(), number, order: self.read, text: text[..=at].to_string() });
+            text = text[at + 1..].trim();
+        }
+        let (first, rest) = split_first(text);
+        if first.is_empty() {
+            return;
+        }
+
+        if depth > MAX_DEPTH {
+            self.error(file, number, "includes or macros nested too deeply".to_string());
+            return;
+        }
+        match first {
+            "include" => {
+                let name = rest.trim_matches('"');
+                let path = Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(name);
+                match fs::read_to_string(&path) {
+                    Ok(source) => self.read(&path.display().to_string(), &source, depth + 1),
+                    Err(err) => self.error(file, number, format!("unable to include {}: {}", name, err))
+                }
+            },
+            "macro" => {
+                let mut words = rest.split(|c: char| c == ',' || c.is_whitespace()).filter(|word| !word.is_empty());
+                match words.next() {
+                    Some(name) if is_identifier(name) => {
+                        let params = words.map(str::to_string).collect();
+                        self.recording = Some((name.to_string(), Macro { params, body: Vec::new() }));
+                    },
+                    _ => self.error(file, number, "macro needs a name".to_string())
+                }
+            },
+            "endm" => self.error(file, number, "endm without macro".to_string()),
+            _ if self.macros.contains_key(first) => {
+                let args = operands(rest);
+                let body = {
+                    let expanded = &self.macros[first];
+                    if args.len() != expanded.params.len() {
+                        Err(format!("macro {} needs {} arguments, got {}", first, expanded.params.len(), args.len()))
+                    } else {
+                        Ok(expanded.body.iter().map(|line| substitute(line, &expanded.params, &args)).collect::<Vec<_>>())
+                    }
+                };
+                match body {
+                    Ok(body) => {
+                        for line in body {
+                            self.statement(file, number, &line, depth + 1);
+                        }
+                    },
+                    Err(err) => self.error(file, number, err)
+                }
+            },
+            _ => self.lines.push(Line { file: file.to_string(), number, order: self.read, text: text.to_string() })
+        }
+    }
+
+    fn finish(mut self) -> Result<Assembly, Vec<AsmError>> {
+        // second pass, find out where every label goes
+        let lines = std::mem::take(&mut self.lines);
+        let mut statements = Vec::new();
+        let mut address = self.origin as usize;
+        for line in lines.iter() {
+            let statement = match parse(&line.text) {
+                Ok(statement) => statement,
+                Err(err) => {
+                    self.line_error(line, err);
+                    continue;
+                }
+            };
+            match statement {
+                Statement::Label(ref name) => {
+                    if self.labels.contains_key(name) || self.constants.contains_key(name) {
+                        self.line_error(line, format!("{} is already defined", name));
+                    }
+                    self.labels.insert(name.clone(), address as u16);
+                },
+                Statement::Constant(ref name, ref value) => {
+                    if self.labels.contains_key(name) || self.constants.contains_key(name) {
+                        self.line_error(line, format!("{} is already defined", name));
+                    }
+                    self.constants.insert(name.clone(), value.clone());
+                },
+                Statement::Bytes(ref values) => address += values.len(),
+                Statement::Words(ref values) => address += 2 * values.len(),
+                Statement::Instruction(_, ref operands) => {
+                    address += if operands.iter().any(|operand| matches!(*operand, Operand::Long(_))) { 4 } else { 2 };
+                }
+            }
+            if address > 0x10000 {
+                self.line_error(line, "program does not fit into memory".to_string());
+                break;
+            }
+            statements.push((line, statement));
+        }
+
+        // third pass, generate the code
+        let mut rom = Vec::new();
+        let mut lines = Vec::new();
+        for (line, statement) in statements {
+            let bytes = match statement {
+                Statement::Label(_) | Statement::Constant(..) => Ok(Vec::new()),
+                Statement::Bytes(values) => values.iter()
+                    .map(|value| self.number(value, -0x80, 0xFF).map(|byte| byte as u8))
+                    .collect(),
+                Statement::Words(values) => values.iter()
+                    .map(|value| self.number(value, -0x8000, 0xFFFF).map(|word| (word as u16).to_be_bytes()))
+                    .collect::<Result<Vec<_>, String>>()
+                    .map(|words| words.concat()),
+                Statement::Instruction(mnemonic, operands) => self.instruction(&mnemonic, &operands)
+            };
+            match bytes {
+                Ok(ref bytes) if bytes.is_empty() => {},
+                Ok(bytes) => {
+                    lines.push((self.origin + rom.len() as u16, line.file.clone(), line.number));
+                    rom.extend(bytes);
+                },
+                Err(err) => self.line_error(line, err)
+            }
+        }
+
+        if !self.errors.is_empty() {
+            // in the order they appear, not the order the passes found them
+            self.errors.sort_by_key(|&(order, _)| order);
+            return Err(self.errors.into_iter().map(|(_, err)| err).collect());
+        }
+        let symbols = self.labels.into_iter().collect();
+        Ok(Assembly { rom, symbols, lines })
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+        use self::Operand::*;
+        let addr = |value: &str| self.number(value, 0, 0xFFF).map(|addr| addr as u16);
+        let byte = |value: &str| self.number(value, -0x80, 0xFF).map(|byte| byte as u8);
+        let nibble = |value: &str| self.number(value, 0, 0xF).map(|n| n as u8);
+        let instruction = match (mnemonic.to_lowercase().as_str(), operands) {
+            ("cls", []) => Instruction::Cls,
+            ("ret", []) => Instruction::Ret,
+            ("scr", []) => Instruction::Scr,
+            ("scl", []) => Instruction::Scl,
+            ("exit", []) => Instruction::Exit,
+            ("low", []) => Instruction::Low,
+            ("high", []) => Instruction::High,
+            ("audio", []) => Instruction::Audio,
+            ("sys", [Value(a)]) => Instruction::Sys(addr(a)?),
+            ("scd", [Value(n)]) => Instruction::Scd(nibble(n)?),
+            ("scu", [Value(n)]) => Instruction::Scu(nibble(n)?),
+            ("plane", [Value(n)]) => Instruction::Plane(nibble(n)?),
+            ("jp", [Value(a)]) => Instruction::Jp(addr(a)?),
+            ("jp", [V(0), Value(a)]) => Instruction::JpV0(addr(a)?),
+            ("call", [Value(a)]) => Instruction::Call(addr(a)?),
+            ("se", [V(x), V(y)]) => Instruction::SeXY { x: *x, y: *y },
+            ("se", [V(x), Value(b)]) => Instruction::SeX { x: *x, byte: byte(b)? },
+            ("sne", [V(x), V(y)]) => Instruction::SneXY { x: *x, y: *y },
+            ("sne", [V(x), Value(b)]) => Instruction::SneX { x: *x, byte: byte(b)? },
+            ("ld", [V(x), V(y)]) => Instruction::LdXY { x: *x, y: *y },
+            ("ld", [V(x), Value(b)]) => Instruction::LdV { x: *x, byte: byte(b)? },
+            ("ld", [I, Value(a)]) => Instruction::LdI(addr(a)?),
+            ("ld", [I, Long(a)]) => {
+                let long = self.number(a, 0, 0xFFFF)? as u16;
+                return self.encode(Instruction::LdILong).map(|mut bytes| {
+                    bytes.extend_from_slice(&long.to_be_bytes());
+                    bytes
+                });
+            },
+            ("ld", [V(x), Dt]) => Instruction::LdXDT(*x),
+            ("ld", [V(x), K]) => Instruction::LdXK(*x),
+            ("ld", [Dt, V(x)]) => Instruction::LdDT(*x),
+            ("ld", [St, V(x)]) => Instruction::LdST(*x),
+            ("ld", [F, V(x)]) => Instruction::LdF(*x),
+            ("ld", [Hf, V(x)]) => Instruction::LdHF(*x),
+            ("ld", [B, V(x)]) => Instruction::LdB(*x),
+            ("ld", [IndirectI, V(x)]) => Instruction::LdIX(*x),
+            ("ld", [V(x), IndirectI]) => Instruction::LdXI(*x),
+            ("ld", [R, V(x)]) => Instruction::LdRX(*x),
+            ("ld", [V(x), R]) => Instruction::LdXR(*x),
+            ("ld", [IndirectI, Range(x, y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("ld", [Range(x, y), IndirectI]) => Instruction::LoadRange { x: *x, y: *y },
+            ("ld", [Pitch, V(x)]) => Instruction::Pitch(*x),
+            ("add", [V(x), V(y)]) => Instruction::AddXY { x: *x, y: *y },
+            ("add", [V(x), Value(b)]) => Instruction::AddX { x: *x, byte: byte(b)? },
+            ("add", [I, V(x)]) => Instruction::AddI(*x),
+            ("or", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("and", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("xor", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("sub", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("subn", [V(x), V(y)]) => Instruction::Subn { x: *x, y: *y },
+            ("shr", [V(x), V(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("shr", [V(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("shl", [V(x), V(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("shl", [V(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("rnd", [V(x), Value(b)]) => Instruction::Rnd { x: *x, byte: byte(b)? },
+            ("drw", [V(x), V(y), Value(n)]) => Instruction::Drw { x: *x, y: *y, n: nibble(n)? },
+            ("skp", [V(x)]) => Instruction::Skp(*x),
+            ("sknp", [V(x)]) => Instruction::Sknp(*x),
+            (name, _) if MNEMONICS.contains(&name) => return Err(format!("invalid operands for {}", name)),
+            (name, _) => return Err(format!("unknown instruction {}", name))
+        };
+        self.encode(instruction)
+    }
+
+    // Goes through the emulator's own encoder, whatever comes out runs as written
+    fn encode(&self, instruction: Instruction) -> Result<Vec<u8>, String> {
+        let opcode = encode(&instruction).ok_or(format!("{} can not be encoded as a single opcode", instruction))?;
+        Ok(opcode.to_be_bytes().to_vec())
+    }
+
+    fn number(&self, expr: &str, min: i64, max: i64) -> Result<i64, String> {
+        let value = self.value(expr, 0)?;
+        if value < min || value > max {
+            return Err(format!("{} is out of range, expected {} to {}", expr, min, max));
+        }
+        Ok(value)
+    }
+
+    // Terms added or subtracted from left to right
+    fn value(&self, expr: &str, depth: usize) -> Result<i64, String> {
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expr.chars().chain(Some('+')) {
+            match c {
+                '+' | '-' => {
+                    let word = term.trim();
+                    if word.is_empty() {
+                        if c == '-' {
+                            sign = -sign;
+                            continue;
+                        }
+                        return Err(format!("invalid expression {}", expr));
+                    }
+                    total += sign * self.term(word, depth)?;
+                    sign = if c == '-' { -1 } else { 1 };
+                    term.clear();
+                },
+                _ => term.push(c)
+            }
+        }
+        Ok(total)
+    }
+
+    fn term(&self, word: &str, depth: usize) -> Result<i64, String> {
+        let parsed = if let Some(hex) = word.strip_prefix('#').or_else(|| word.strip_prefix("0x")) {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = word.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok()
+        } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+            word.parse().ok()
+        } else if let Some(address) = self.labels.get(word) {
+            Some(*address as i64)
+        } else if let Some(value) = self.constants.get(word) {
+            if depth > MAX_DEPTH {
+                return Err(format!("{} is defined in terms of itself", word));
+            }
+            return self.value(value, depth + 1);
+        } else if is_identifier(word) {
+            return Err(format!("unknown symbol {}", word));
+        } else {
+            None
+        };
+        parsed.ok_or_else(|| format!("invalid number {}", word))
+    }
+}
+
+const MNEMONICS: [&str; 29] = [
+    "cls", "ret", "scr", "scl", "exit", "low", "high", "audio", "sys", "scd", "scu", "plane", "jp", "call",
+    "se", "sne", "ld", "add", "or", "and", "xor", "sub", "subn", "shr", "shl", "rnd", "drw", "skp", "sknp"
+];
+
+fn parse(text: &str) -> Result<Statement, String> {
+    if let Some(label) = text.strip_suffix(':') {
+        return Ok(Statement::Label(label.to_string()));
+    }
+    if let Some(at) = text.find('=') {
+        let name = text[..at].trim();
+        if !is_identifier(name) {
+            return Err(format!("invalid constant name {}", name));
+        }
+        return Ok(Statement::Constant(name.to_string(), text[at + 1..].trim().to_string()));
+    }
+    let (mnemonic, rest) = split_first(text);
+    let operands = operands(rest);
+    match mnemonic {
+        "db" | "dw" if operands.is_empty() => Err(format!("{} needs at least one value", mnemonic)),
+        "db" => Ok(Statement::Bytes(operands)),
+        "dw" => Ok(Statement::Words(operands)),
+        _ => Ok(Statement::Instruction(mnemonic.to_string(), operands.iter().map(|operand| Operand::parse(operand)).collect()))
+    }
+}
+
+fn split_first(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(at) => (&text[..at], text[at..].trim()),
+        None => (text, "")
+    }
+}
+
+fn operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(|operand| operand.trim().to_string()).collect()
+}
+
+fn is_identifier(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.') &&
+        word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (at, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..at],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Replaces whole words only, so a parameter x leaves Vx and x2 alone
+fn substitute(line: &str, params: &[String], args: &[String]) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        match params.iter().position(|param| param == word) {
+            Some(i) => out.push_str(&args[i]),
+            None => out.push_str(word)
+        }
+        word.clear();
+    };
+    for c in line.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+pub fn assemble(name: &str, source: &str, origin: u16) -> Result<Assembly, Vec<AsmError>> {
+    Assembler::new(origin).assemble(name, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use super::super::disasm;
+    use super::super::instructions::decode;
+
+    fn rom(source: &str) -> Vec<u8> {
+        match assemble("test.8s", source, 0x200) {
+            Ok(assembly) => assembly.rom,
+            Err(errors) => panic!("{:?}", errors)
+        }
+    }
+
+    #[test]
+    fn reads_what_the_debugger_prints() {
+        // every opcode that decodes to a real instruction, printed and assembled again
+        let opcodes: Vec<u16> = (0..=0xFFFFu16)
+            .filter(|&opcode| !matches!(decode(opcode), Instruction::Unknown(_) | Instruction::LdILong))
+            .collect();
+        // in chunks, all of them would not fit into memory at once
+        for chunk in opcodes.chunks(0x1000) {
+            let source: Vec<String> = chunk.iter().map(|&opcode| decode(opcode).to_string()).collect();
+            let expected: Vec<u8> = chunk.iter().flat_map(|opcode| opcode.to_be_bytes().to_vec()).collect();
+            assert_eq!(rom(&source.join("\n")), expected);
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            SPEED = 2 + 1        ; constants can be expressions
+            start:
+                ld I, sprite
+                ld V0, SPEED
+            loop: add V0, -1
+                jp loop
+                ld I, long sprite + 1
+            sprite:
+                db 0b11110000, #90, 255
+                dw #1234, start";
+        assert_eq!(rom(source), vec![
+            0xA2, 0x0C, 0x60, 0x03, 0x70, 0xFF, 0x12, 0x04, 0xF0, 0x00, 0x02, 0x0D,
+            0xF0, 0x90, 0xFF, 0x12, 0x34, 0x02, 0x00
+        ]);
+        let assembly = assemble("test.8s", source, 0x200).ok().unwrap();
+        let symbol_file = assembly.symbol_file();
+        assert!(symbol_file.starts_with("0x0200 start\n0x0200 test.8s:4\n0x0202 test.8s:5\n0x0204 loop\n"));
+        let symbols = Symbols::parse(&symbol_file);
+        assert_eq!(symbols.label("sprite"), Some(0x20C));
+        assert_eq!(symbols.label_for(0x206), Some("loop"));
+        assert_eq!(symbols.line_for(0x20C), Some(("test.8s", 10)));
+        assert_eq!(symbols.address_for("/home/me/test.8s", 6), Some(0x204));
+    }
+
+    #[test]
+    fn expands_macros() {
+        let source = "
+            macro move x, y
+                ld x, y
+            endm
+            move V1, V2
+            move V3, #10";
+        assert_eq!(rom(source), vec![0x81, 0x20, 0x63, 0x10]);
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = env::temp_dir().join(format!("rip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.8s"), "ret\n").unwrap();
+        fs::write(dir.join("main.8s"), "call sub\njp #200\nsub: include \"lib.8s\"\n").unwrap();
+        let assembly = Assembler::new(0x200).assemble_file(&dir.join("main.8s"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assembly.ok().unwrap().rom, vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn orders_errors_in_included_files_where_they_are_included() {
+        let dir = env::temp_dir().join(format!("rip8-asm-errors-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.8s"), "ld V0, 256\n").unwrap();
+        fs::write(dir.join("main.8s"), "frob\ninclude \"a.8s\"\njp nowhere\n").unwrap();
+        let errors = Assembler::new(0x200).assemble_file(&dir.join("main.8s")).err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let places: Vec<(bool, usize)> = errors.iter().map(|err| (err.file.ends_with("main.8s"), err.line)).collect();
+        assert_eq!(places, vec![(true, 1), (false, 1), (true, 3)]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let errors = assemble("bad.8s", "cls\nld V0, 256\nfrob V1\njp nowhere\nld I, V1\nsys #e0\nx:\nx:", 0x200)
+            .err().unwrap();
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(messages, vec![
+            "bad.8s:2: 256 is out of range, expected -128 to 255",
+            "bad.8s:3: unknown instruction frob",
+            "bad.8s:4: unknown symbol nowhere",
+            "bad.8s:5: invalid operands for ld",
+            "bad.8s:6: sys #e0 can not be encoded as a single opcode",
+            "bad.8s:8: x is already defined"
+        ]);
+    }
+
+    #[test]
+    fn sorts_errors_from_every_pass_by_line() {
+        // endm is found while reading, the duplicate label while placing labels, frob while encoding
+        let errors = assemble("bad.8s", "frob
+a:
+a:
+endm", 0x200).err().unwrap();
+        let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn reassembles_disassembled_roms() {
+        let rom = vec![
+            0x00, 0xE0, 0xA2, 0x0C, 0x22, 0x0A, 0xD0, 0x12, 0x12, 0x06,
+            0x00, 0xEE, 0xF0, 0x90, 0x12, 0x34, 0x56
+        ];
+        let listing = disasm::disassemble(&rom, 0x200).unwrap();
+        assert_eq!(assemble("listing", &listing, 0x200).ok().unwrap().rom, rom);
+    }
+}
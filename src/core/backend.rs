@@ -0,0 +1,31 @@
+use super::keyboard::Keyboard;
+use super::screen::Screen;
+
+// What to play while the sound timer runs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tone {
+    pub playing: bool,             // the sound timer is non-zero
+    pub pattern: Option<[u8; 16]>, // XO-CHIP audio pattern, a plain square wave if None
+    pub rate: f32                  // pattern playback rate in bits per second
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone { playing: false, pattern: None, rate: 4000.0 }
+    }
+}
+
+// Shows the screen, called once at the end of every frame
+pub trait DisplaySink {
+    fn present(&mut self, screen: &Screen);
+}
+
+// Updates the hex keypad, called once at the start of every frame
+pub trait InputSource {
+    fn poll(&mut self, keyboard: &mut Keyboard);
+}
+
+// Plays the current tone, called once at the end of every frame
+pub trait AudioSink {
+    fn play(&mut self, tone: &Tone);
+}
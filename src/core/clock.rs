@@ -0,0 +1,149 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::registers::TIMER_RATE;
+
+const MAX_LAG_FRAMES: u32 = 5; // fall further behind than this and the clock gives up catching up
+
+// Paces emulation to TIMER_RATE frames per second against the monotonic clock. Every frame is
+// scheduled relative to the previous deadline instead of the time it finished, so sleep jitter
+// does not add up.
+pub struct Clock {
+    speed: f64,
+    uncapped: bool,
+    next_frame: Option<Instant>,
+    window_start: Instant,
+    window_frames: u32,
+    window_instructions: u64,
+    frames_per_second: f64,
+    instructions_per_second: f64
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            speed: 1.0,
+            uncapped: false,
+            next_frame: None,
+            window_start: Instant::now(),
+            window_frames: 0,
+            window_instructions: 0,
+            frames_per_second: 0.0,
+            instructions_per_second: 0.0
+        }
+    }
+
+    // Fast forward multiplier, 2.0 runs twice as many frames per second
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.01);
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // Never sleeps, for benchmarking
+    pub fn set_uncapped(&mut self, uncapped: bool) {
+        self.uncapped = uncapped;
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs(1).div_f64(TIMER_RATE as f64 * self.speed)
+    }
+
+    // Call once per emulated frame with the number of instructions it ran. Sleeps until the next
+    // frame is due unless the clock is uncapped.
+    pub fn frame_done(&mut self, instructions: u64) {
+        let now = Instant::now();
+        self.record(now, instructions);
+        if self.uncapped {
+            return;
+        }
+        let deadline = self.schedule(now);
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+    }
+
+    // Achieved speed over the last second
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions_per_second
+    }
+
+    fn schedule(&mut self, now: Instant) -> Instant {
+        let frame = self.frame_duration();
+        let next = match self.next_frame {
+            Some(next) if now < next + frame * MAX_LAG_FRAMES => next + frame,
+            _ => now + frame
+        };
+        self.next_frame = Some(next);
+        next
+    }
+
+    fn record(&mut self, now: Instant, instructions: u64) {
+        self.window_frames += 1;
+        self.window_instructions += instructions;
+        let elapsed = now.duration_since(self.window_start).as_secs_f64();
+        if elapsed >= 1.0 {
+            self.frames_per_second = self.window_frames as f64 / elapsed;
+            self.instructions_per_second = self.window_instructions as f64 / elapsed;
+            self.window_start = now;
+            self.window_frames = 0;
+            self.window_instructions = 0;
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines_do_not_drift() {
+        let mut clock = Clock::new();
+        let start = Instant::now();
+        let first = clock.schedule(start);
+        // finishing the next frame late does not push the one after it back
+        let second = clock.schedule(first + Duration::from_millis(3));
+        assert_eq!(second, first + clock.frame_duration());
+    }
+
+    #[test]
+    fn gives_up_catching_up_when_too_far_behind() {
+        let mut clock = Clock::new();
+        let start = Instant::now();
+        clock.schedule(start);
+        let late = start + Duration::from_secs(1);
+        assert_eq!(clock.schedule(late), late + clock.frame_duration());
+    }
+
+    #[test]
+    fn speed_shortens_frames() {
+        let mut clock = Clock::new();
+        let normal = clock.frame_duration();
+        clock.set_speed(2.0);
+        let doubled = clock.frame_duration() * 2;
+        assert!(normal.abs_diff(doubled) <= Duration::from_nanos(1));
+    }
+
+    #[test]
+    fn reports_achieved_speed() {
+        let mut clock = Clock::new();
+        let start = clock.window_start;
+        for frame in 1..=60 {
+            clock.record(start + Duration::from_millis(frame * 1000 / 60), 10);
+        }
+        assert_eq!(clock.frames_per_second().round(), 60.0);
+        assert_eq!(clock.instructions_per_second().round(), 600.0);
+    }
+}
@@ -0,0 +1,148 @@
+use std::cell::Cell;
+
+use super::dump;
+
+// How a byte of memory was used, as bit flags
+pub const FETCHED: u8 = 1; // as part of an instruction
+pub const READ: u8 = 2;    // as data, by drw, ld B, ld Vx, [I] and friends
+pub const WRITTEN: u8 = 4;
+
+const ROW: usize = 64;  // bytes per row of the overlay image
+const SCALE: usize = 4; // pixels per byte, each way
+// Indexed by the flags, never touched memory that is not zero comes last
+const PALETTE: [[u8; 3]; 9] = [
+    [0x00, 0x00, 0x00], // untouched
+    [0x30, 0xC0, 0x30], // code, green
+    [0x30, 0x60, 0xF0], // data, blue
+    [0x30, 0xC0, 0xC0], // code read as data, cyan
+    [0xE0, 0x30, 0x30], // written, red
+    [0xF0, 0xE0, 0x30], // self-modifying code, yellow
+    [0xC0, 0x40, 0xC0], // variables, magenta
+    [0xFF, 0xFF, 0xFF], // everything
+    [0x40, 0x40, 0x40]  // untouched but not zero, grey
+];
+
+// Every byte of Ram with the ways it was accessed since tracking started. Cells because reads
+// only borrow the memory.
+pub struct Coverage {
+    flags: Vec<Cell<u8>>
+}
+
+impl Coverage {
+    pub fn new(size: usize) -> Coverage {
+        Coverage { flags: vec![Cell::new(0); size] }
+    }
+
+    pub fn mark(&self, address: usize, access: u8) {
+        if let Some(flags) = self.flags.get(address) {
+            flags.set(flags.get() | access);
+        }
+    }
+
+    pub fn get(&self, address: usize) -> u8 {
+        self.flags.get(address).map_or(0, Cell::get)
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    // Runs of bytes used the same way, untouched ones left out:
+    // 0x0200-0x0233    52  code
+    pub fn map(&self) -> String {
+        let count = |access: u8| self.flags.iter().filter(|flags| flags.get() & access != 0).count();
+        let mut map = format!("{} bytes fetched, {} read, {} written, {} both fetched and written\n",
+                              count(FETCHED), count(READ), count(WRITTEN),
+                              self.flags.iter().filter(|flags| flags.get() & (FETCHED | WRITTEN) == FETCHED | WRITTEN).count());
+        let mut start = 0;
+        while start < self.flags.len() {
+            let flags = self.get(start);
+            let mut end = start;
+            while end + 1 < self.flags.len() && self.get(end + 1) == flags {
+                end += 1;
+            }
+            if flags != 0 {
+                map.push_str(&format!("0x{:04x}-0x{:04x} {:>5}  {}\n", start, end, end - start + 1, describe(flags)));
+            }
+            start = end + 1;
+        }
+        map
+    }
+
+    // Memory as a grid of coloured squares, ROW bytes to a line, see PALETTE for the colours
+    pub fn overlay(&self, ram: &[u8]) -> Vec<u8> {
+        let (width, height) = (ROW * SCALE, self.flags.len().div_ceil(ROW) * SCALE);
+        let mut pixels = vec![0; width * height];
+        for (address, flags) in self.flags.iter().enumerate() {
+            let colour = match flags.get() {
+                0 if ram.get(address).is_some_and(|&byte| byte != 0) => 8,
+                flags => flags
+            };
+            let (x, y) = (address % ROW * SCALE, address / ROW * SCALE);
+            for row in 0..SCALE {
+                let line = (y + row) * width + x;
+                for pixel in pixels[line..line + SCALE].iter_mut() {
+                    *pixel = colour;
+                }
+            }
+        }
+        dump::indexed_png(width, height, &PALETTE, &pixels)
+    }
+}
+
+fn describe(flags: u8) -> String {
+    let mut words = Vec::new();
+    if flags & FETCHED != 0 {
+        words.push("code");
+    }
+    if flags & READ != 0 {
+        words.push("data");
+    }
+    if flags & WRITTEN != 0 {
+        words.push("written");
+    }
+    words.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cpu::Cpu;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    #[test]
+    fn marks_code_data_and_writes() {
+        let rom = vec![
+            0xA2, 0x0A, // 200: ld I, 20a
+            0xD0, 0x02, // 202: drw V0, V0, 2
+            0xF0, 0x33, // 204: ld B, V0
+            0x12, 0x06, // 206: jp 206
+            0x00, 0x00,
+            0xF0, 0x90, // 20a: sprite, then overwritten by ld B
+            0x12
+        ];
+        let mut cpu = Cpu::new(Rom::from_bytes(rom), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.track_coverage();
+        for _ in 0..5 {
+            cpu.tick().unwrap();
+        }
+        let coverage = cpu.ram().coverage().unwrap();
+        assert_eq!(coverage.get(0x200), FETCHED);
+        assert_eq!(coverage.get(0x208), 0);
+        assert_eq!(coverage.get(0x20A), READ | WRITTEN);
+        assert_eq!(coverage.get(0x20C), WRITTEN);
+        assert_eq!(coverage.map(), "8 bytes fetched, 2 read, 3 written, 0 both fetched and written
+0x0200-0x0207     8  code
+0x020a-0x020b     2  data+written
+0x020c-0x020c     1  written
+");
+        let png = coverage.overlay(&cpu.ram().ram);
+        assert_eq!(png[16..24], [0, 0, 1, 0, 0, 0, 1, 0]); // 256x256
+    }
+}
@@ -1,17 +1,21 @@
-extern crate rand;
-
-use std::io::{self, Write, BufRead};
-use std::process;
-use std::panic;
-use self::rand::Rng;
-
-use super::ram::Ram;
+use super::backend::{AudioSink, DisplaySink, InputSource, Tone};
+use super::error::Rip8Error;
+use super::ram::{Ram, MEMORY_SIZE, XO_MEMORY_SIZE};
 use super::rom::Rom;
 use super::keyboard::Keyboard;
-use super::registers::{Registers, SOUND_TIMER, DELAY_TIMER};
+use super::registers::{Registers, STACK_SIZE};
 use super::instruction::Instruction;
-use super::instructions::Instructions;
-use super::screen::Screen;
+use super::instructions;
+use super::quirks::{IncrementI, Quirks};
+use super::random::{Random, SplitMix};
+use super::rpl::RPL_FLAGS;
+use super::screen::{Screen, PLANES, HIRES_WIDTH, HIRES_HEIGHT};
+use super::state::{self, StateReader, StateWriter};
+use super::rewind::Rewind;
+use super::movie::{Movie, Tape};
+use super::debugger::{Debugger, Monitor};
+use super::trace::Trace;
+use super::profile::Profile;
 
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, //0
@@ -31,535 +35,1666 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, //E
     0xF0, 0x80, 0xF0, 0x80, 0x80, //F
 ];
+const BIG_FONT_ADDRESS: usize = 0x50; // right behind FONT_SET
+const BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, //0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, //1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, //4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, //7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, //B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, //C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, //F
+];
+const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64; // 4000Hz playback rate
+pub const INSTRUCTIONS_PER_FRAME: usize = 10; // ~600Hz at 60 frames per second
 
 pub struct Cpu {
     ram: Ram,
     rom: Rom,
     pub keyboard: Keyboard,
-    pub screen: Screen,
+    screen: Screen,
     registers: Registers,
-    instructions: Instructions,
+    quirks: Quirks,
+    random: Box<dyn Random>,
+    rewind: Option<Rewind>,
+    tape: Option<Tape>,
+    instructions_per_frame: usize,
+    vblank_wait: bool,
+    cycles: u64,
+    frames: u64,
+    exited: bool,
+    rpl: [u8; RPL_FLAGS],
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
     debug: bool,
-    interactive: bool,
-    debug_break: bool,
-    break_point: u16,
-    debug_run: bool
+    monitor: Option<Box<dyn Monitor>>, // the interactive debugger or a remote one
+    trace: Option<Trace>,
+    profile: Option<Profile>
 }
 
 impl Cpu {
-    pub fn new(rom: String, debug: bool, interactive: bool) -> Cpu {
+    pub fn new(rom: Rom, quirks: Quirks, debug: bool, interactive: bool) -> Cpu {
         Cpu {
-            ram: Ram::new(),
-            rom: Rom::new(rom),
+            ram: Ram::with_size(if quirks.xo_chip { XO_MEMORY_SIZE } else { MEMORY_SIZE }),
+            rom,
             keyboard: Keyboard::new(),
             screen: Screen::new(),
             registers: Registers::new(),
-            instructions: Instructions::new(),
+            quirks,
+            random: Box::new(SplitMix::from_entropy()),
+            rewind: None,
+            tape: None,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            vblank_wait: false,
+            cycles: 0,
+            frames: 0,
+            exited: false,
+            rpl: [0; RPL_FLAGS],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
             debug,
-            interactive,
-            debug_break: false,
-            break_point: 0,
-            debug_run: false
+            monitor: if interactive { Some(Box::new(Debugger::new())) } else { None },
+            trace: None,
+            profile: None
         }
     }
 
     pub fn load_font(&mut self) {
-        for i in 0..FONT_SET.len() {
-            self.ram.write(i, FONT_SET[i]);
+        for (i, byte) in FONT_SET.iter().enumerate() {
+            self.ram.write(i, *byte);
+        }
+        for (i, byte) in BIG_FONT_SET.iter().enumerate() {
+            self.ram.write(BIG_FONT_ADDRESS + i, *byte);
         }
     }
 
-    pub fn load_rom(&mut self) {
+    pub fn load_rom(&mut self) -> Result<(), Rip8Error> {
         let pc = self.registers.pc as usize;
-        for i in 0..self.rom.rom.len() {
-            self.ram.write(pc + i, self.rom.rom[i])
+        let size = self.rom.rom.len();
+        if pc + size > self.ram.size() {
+            return Err(Rip8Error::RomTooLarge { size, max: self.ram.size() - pc });
+        }
+        for (i, byte) in self.rom.rom.iter().enumerate() {
+            self.ram.write(pc + i, *byte);
+        }
+        Ok(())
+    }
+
+    // Runs one instruction, after letting the debugger have a look if one is attached.
+    pub fn tick(&mut self) -> Result<(), Rip8Error> {
+        if let Some(mut monitor) = self.monitor.take() {
+            monitor.before_instruction(self);
+            self.monitor = Some(monitor);
+        }
+        self.step()
+    }
+
+    // Runs exactly one instruction, never prompts. Does nothing once the ROM exited.
+    pub fn step(&mut self) -> Result<(), Rip8Error> {
+        if self.exited {
+            return Ok(());
+        }
+        let pc = self.registers.pc;
+        let instr = match self.ram.fetch(pc as usize) {
+            Some(instr) => instr,
+            None => return Err(Rip8Error::MemoryOutOfRange { pc, opcode: 0, address: pc as usize })
+        };
+        self.cycles += 1;
+        self.process_instruction(instr)
+    }
+
+    // Runs one 60Hz frame worth of instructions and counts the timers down once.
+    // A DRW ends the frame early if the display wait quirk is on.
+    pub fn run_frame(&mut self) -> Result<(), Rip8Error> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    // Like run_frame, but checks stop before every instruction. Returns true and leaves the frame
+    // unfinished, without ticking the timers, as soon as it does.
+    pub fn run_frame_until<F: FnMut(&Cpu) -> bool>(&mut self, mut stop: F) -> Result<bool, Rip8Error> {
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.push(self.save_state());
+            self.rewind = Some(rewind);
+        }
+        self.vblank_wait = false;
+        for _ in 0..self.instructions_per_frame {
+            if stop(self) {
+                return Ok(true);
+            }
+            self.tick()?;
+            if self.vblank_wait {
+                break;
+            }
+        }
+        self.tick_timers();
+        self.frames += 1;
+        Ok(false)
+    }
+
+    // One frame driven by a frontend: poll the keypad, run, then show the screen and play the tone
+    pub fn run_frame_with<F>(&mut self, frontend: &mut F) -> Result<(), Rip8Error>
+        where F: InputSource + DisplaySink + AudioSink + ?Sized {
+        self.poll_input(frontend);
+        self.run_frame()?;
+        frontend.present(&self.screen);
+        frontend.play(&self.tone());
+        Ok(())
+    }
+
+    // Samples the keypad for the next frame. A movie being recorded stores it, one being played back
+    // replaces it.
+    pub fn poll_input<I: InputSource + ?Sized>(&mut self, input: &mut I) {
+        input.poll(&mut self.keyboard);
+        if let Some(ref mut tape) = self.tape {
+            tape.sample(self.frames, &mut self.keyboard);
+        }
+    }
+
+    // Starts recording the keypad, from power on
+    pub fn record_movie(&mut self) {
+        let movie = Movie::new(&self.rom.rom, self.quirks, self.random.seed(), self.instructions_per_frame);
+        self.tape = Some(Tape::Recording(movie));
+    }
+
+    // Replays a movie from power on, with the quirks, seed and speed it was recorded with
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), Rip8Error> {
+        if movie.rom_hash != state::rom_hash(&self.rom.rom) {
+            return Err(Rip8Error::RomMismatch);
+        }
+        if movie.quirks.xo_chip != self.quirks.xo_chip {
+            self.ram = Ram::with_size(if movie.quirks.xo_chip { XO_MEMORY_SIZE } else { MEMORY_SIZE });
+            self.load_font();
+            self.load_rom()?;
+        }
+        self.quirks = movie.quirks;
+        self.random.reseed(movie.seed);
+        self.set_instructions_per_frame(movie.instructions_per_frame);
+        self.tape = Some(Tape::Playing(movie));
+        Ok(())
+    }
+
+    // The movie being recorded or played back
+    pub fn movie(&self) -> Option<&Movie> {
+        self.tape.as_ref().map(Tape::movie)
+    }
+
+    pub fn movie_finished(&self) -> bool {
+        self.tape.as_ref().is_some_and(|tape| tape.finished(self.frames))
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: usize) {
+        self.instructions_per_frame = instructions.max(1);
+    }
+
+    // Instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Frames run through run_frame so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Counts DT and ST down, embedders driving the CPU through step() call this at 60Hz themselves.
+    pub fn tick_timers(&mut self) {
+        self.registers.tick_timers();
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    // Replaces the random number source used by CXKK
+    pub fn set_random(&mut self, random: Box<dyn Random>) {
+        self.random = random;
+    }
+
+    // Seed that reproduces every random number from here on
+    pub fn seed(&self) -> u64 {
+        self.random.seed()
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.random.reseed(seed);
+    }
+
+    // True once the ROM ran 00FD
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    // Stops the machine as if the ROM ran exit
+    pub fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    // Replaces the interactive debugger, if there was one
+    pub fn attach(&mut self, monitor: Box<dyn Monitor>) {
+        self.monitor = Some(monitor);
+    }
+
+    // Lets the monitor know the machine stopped for good and drops it
+    pub fn detach(&mut self, exit_code: i32) {
+        if let Some(mut monitor) = self.monitor.take() {
+            monitor.ended(self, exit_code);
+        }
+    }
+
+    // Logs every instruction from now on
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    // Stops tracing, call Trace::finish to flush it
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    // Counts every instruction from now on
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // Records how every byte of memory gets used from now on, see Ram::coverage
+    pub fn track_coverage(&mut self) {
+        self.ram.track_coverage();
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; RPL_FLAGS] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; RPL_FLAGS]) {
+        self.rpl = flags;
+    }
+
+    // Snapshot of the whole machine, see state.rs for the header
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(state::rom_hash(&self.rom.rom));
+        state.u8(self.quirks.bits());
+        state.u64(self.random.seed());
+        state.u32(self.ram.size() as u32);
+        state.bytes(&self.ram.ram);
+
+        let registers = &self.registers;
+        state.u16(registers.pc);
+        state.u8(registers.sp);
+        state.u16(registers.i);
+        state.bytes(&registers.v);
+        for address in registers.stack.iter() {
+            state.u16(*address);
         }
+        state.u8(registers.delay_timer);
+        state.u8(registers.sound_timer);
+
+        state.bool(self.screen.hires());
+        state.u8(self.screen.planes());
+        // two bits per pixel, column by column
+        let pixels: Vec<u8> = self.screen.screen.iter().flat_map(|column| column.chunks(4))
+            .map(|four| four.iter().enumerate().fold(0, |byte, (i, colour)| byte | (colour & 0x3) << (i * 2)))
+            .collect();
+        state.bytes(&pixels);
+        let keys = self.keyboard.keyboard.iter().enumerate()
+            .fold(0u16, |keys, (key, &pressed)| keys | (pressed as u16) << key);
+        state.u16(keys);
+
+        state.bytes(&self.rpl);
+        state.bool(self.audio_pattern.is_some());
+        state.bytes(&self.audio_pattern.unwrap_or([0; AUDIO_PATTERN_SIZE]));
+        state.u8(self.pitch);
+        state.bool(self.exited);
+        state.u64(self.cycles);
+        state.u64(self.frames);
+        state.finish()
     }
 
-    pub fn init(&mut self) {
-        self.registers.start_delay_timer();
-        self.registers.start_sound_timer();
+    // Keeps a snapshot from the start of each of the last frames frames, 0 turns rewinding off
+    pub fn set_rewind_frames(&mut self, frames: usize) {
+        self.rewind = if frames > 0 { Some(Rewind::new(frames)) } else { None };
     }
 
-    pub fn tick(&mut self) {
-        if !self.process_debugger() {
-            return;
+    // Goes back to the start of the last frame, false once there is no history left. A snapshot
+    // that does not load is dropped and leaves the machine as it was.
+    pub fn rewind(&mut self) -> Result<bool, Rip8Error> {
+        let state = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(state) => state,
+            None => return Ok(false)
+        };
+        self.load_state(&state)?;
+        Ok(true)
+    }
+
+    // Restores a snapshot taken with the same ROM. Nothing changes unless the whole state is valid.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), Rip8Error> {
+        let mut state = StateReader::new(bytes, state::rom_hash(&self.rom.rom))?;
+        let quirks = Quirks::from_bits(state.u8()?);
+        let seed = state.u64()?;
+        let memory_size = if quirks.xo_chip { XO_MEMORY_SIZE } else { MEMORY_SIZE };
+        if state.u32()? as usize != memory_size {
+            return Err(Rip8Error::InvalidState("memory size does not match the quirks"));
+        }
+        let mut ram = vec![0; memory_size];
+        state.bytes(&mut ram)?;
+
+        let mut registers = Registers::new();
+        registers.pc = state.u16()?;
+        registers.sp = state.u8()?;
+        registers.i = state.u16()?;
+        state.bytes(&mut registers.v)?;
+        for address in registers.stack.iter_mut() {
+            *address = state.u16()?;
+        }
+        registers.delay_timer = state.u8()?;
+        registers.sound_timer = state.u8()?;
+        if registers.sp as usize > STACK_SIZE {
+            return Err(Rip8Error::InvalidState("stack pointer out of range"));
+        }
+
+        let mut screen = Screen::new();
+        screen.set_hires(state.bool()?);
+        screen.select_planes(state.u8()?);
+        let mut pixels = [0; HIRES_WIDTH * HIRES_HEIGHT / 4];
+        state.bytes(&mut pixels)?;
+        for (i, colour) in screen.screen.iter_mut().flat_map(|column| column.iter_mut()).enumerate() {
+            *colour = (pixels[i / 4] >> ((i % 4) * 2)) & 0x3;
         }
-        let instr = self.ram.read(self.registers.pc as usize);
-        self.process_instruction(instr);
+        let keys = state.u16()?;
+
+        let mut rpl = [0; RPL_FLAGS];
+        state.bytes(&mut rpl)?;
+        let has_pattern = state.bool()?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        state.bytes(&mut pattern)?;
+        let pitch = state.u8()?;
+        let exited = state.bool()?;
+        let cycles = state.u64()?;
+        let frames = state.u64()?;
+        state.finish()?;
+
+        self.quirks = quirks;
+        self.random.reseed(seed);
+        self.ram.ram = ram;
+        self.registers = registers;
+        self.screen = screen;
+        for key in 0..16 {
+            self.keyboard.keyboard[key] = keys & (1 << key) != 0;
+        }
+        self.rpl = rpl;
+        self.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = pitch;
+        self.exited = exited;
+        self.cycles = cycles;
+        self.frames = frames;
+        self.vblank_wait = false;
+        Ok(())
+    }
+
+    // XO-CHIP audio: a 1 bit sample buffer, None until the ROM loads one
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    // Playback rate of the audio pattern in bits per second
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // The opcode at PC, the next one to run
+    pub fn opcode(&self) -> Option<u16> {
+        self.ram.read(self.registers.pc as usize)
     }
 
-    fn process_instruction(&mut self, instr: u16) {
-        let mut opcode = instr & 0xF000;
-        if instr == 0xE0 || instr == 0xEE {
-            opcode = instr; // CHIP8 has 2 instructions starting with 00 which does not get parsed, so let's check for them manually.
-        } else if opcode == 0x8000 {
-            opcode = instr & 0xF00F; // The CHIP8 does also have a number of opcodes starting with 8, identifiable by the last nibble.
-        } else if opcode == 0xF000 || opcode == 0xE000 {
-            opcode = instr & 0xF0FF; // CHIP8 has a series of opcodes which start with F and E, hence preserving the last byte make them identifiable.
+    pub fn tone(&self) -> Tone {
+        Tone {
+            playing: self.registers.sound_timer > 0,
+            pattern: self.audio_pattern,
+            rate: self.playback_rate()
+        }
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    // For debuggers, the machine is not checked for consistency afterwards
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+
+    fn process_instruction(&mut self, instr: u16) -> Result<(), Rip8Error> {
+        let instruction = instructions::decode(instr);
+        self.print_debug_info(&instruction);
+        if let Some(ref mut trace) = self.trace {
+            let long = self.ram.read(self.registers.pc as usize + 2).unwrap_or(0);
+            trace.record(self.frames, self.cycles, &self.registers, instr, &instruction, long);
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.record(self.registers.pc, self.registers.sp, instr, &instruction);
+        }
+        if !self.quirks.xo_chip && instructions::is_xo_chip(&instruction) {
+            return Err(Rip8Error::InvalidOpcode { pc: self.registers.pc, opcode: instr });
         }
-        let instruction = self.instructions.parse(opcode);
-        let panic_pc = self.registers.pc.clone();
-        let panic_registers = self.registers.clone();
-        panic::set_hook(Box::new(move |_| {
-            println!("\nCPU panicked at 0x{:x}", panic_pc);
-            println!("Memory dump at 0x{:x}: {:x}", panic_pc, instr);
-            println!("Parsed instruction at 0x{:x}: {:?}", panic_pc, instruction);
-            println!("Register dump at 0x{:x}: {:#?}", panic_pc, panic_registers);
-        }));
         match instruction {
-            Instruction::JP => {
+            Instruction::Sys(_) => {
+                // machine code routine on the original hardware, ignored by every interpreter since
+                self.registers.step();
+            },
+            Instruction::Jp(addr) => {
                 // Jump to address
-                let addr = self.instructions.parse_address(instr);
-                self.print_debug_info(instruction, addr, 0, 0);
-
-                self.registers.jump(addr as u16);
+                self.registers.jump(addr);
             },
-            Instruction::LdI => {
+            Instruction::LdI(addr) => {
                 // set index register to address
-                let addr = self.instructions.parse_address(instr);
-                self.print_debug_info(instruction, addr, 0, 0);
-
                 self.registers.i = addr;
                 self.registers.step();
             },
-            Instruction::LdV => {
+            Instruction::LdV { x, byte } => {
                 // set Vx to value
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let value = self.instructions.parse_last(instr);
-                self.print_debug_info(instruction, x as u16, value as u16, 0);
-
-                self.registers.v[x] = value;
+                self.registers.v[x as usize] = byte;
                 self.registers.step();
             },
-            Instruction::DRW => {
+            Instruction::Drw { x, y, n } => {
                 // set pixels
-                let _x = self.instructions.parse_nibble(1, instr) as usize;
-                let _y = self.instructions.parse_nibble(2, instr) as usize;
-                let n = self.instructions.parse_nibble(3, instr) as usize;
-                self.print_debug_info(instruction, _x as u16, _y as u16, n as u16);
-
-                let vx = self.registers.v[_x] as usize;
-                let vy = self.registers.v[_y] as usize;
+                // the start position always wraps, the rest of the sprite either wraps or gets clipped
+                let (width, height) = (self.screen.width(), self.screen.height());
+                let vx = self.registers.v[x as usize] as usize % width;
+                let vy = self.registers.v[y as usize] as usize % height;
                 self.registers.v[0xF] = 0;
 
-                let index = self.registers.i as usize;
-                for y in 0..n {
-                    let row = self.ram.read_byte(index + y);
-                    for x in 0..8 {
-                        let pos_x = (vx + x) % 64;
-                        let pos_y = (vy + y) % 32;
-                        let sprite_pixel = if row & 0x80 >> x != 0 {
-                            true
-                        } else {
-                            false
-                        };
-                        let pixel = if self.screen.screen[pos_x][pos_y] == 1 {
-                            true
-                        } else {
-                            false
-                        };
-                        let new_pixel = sprite_pixel ^ pixel;
-                        self.screen.screen[pos_x][pos_y] = new_pixel as u8;
-                        if pixel && sprite_pixel {
-                            self.registers.v[0xF] = 1;
+                // n = 0 is a 16x16 SUPER-CHIP sprite with 2 bytes per row
+                let (rows, cols) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+                // with both XO-CHIP planes selected the second plane's sprite follows the first one
+                let mut index = self.registers.i as usize;
+                for plane in 1..=PLANES {
+                    if self.screen.planes() & plane == 0 {
+                        continue;
+                    }
+                    for row_y in 0..rows {
+                        for row_x in 0..cols {
+                            let row = self.read_byte(instr, index + row_y * cols / 8 + row_x / 8)?;
+                            if row & 0x80 >> (row_x % 8) == 0 {
+                                continue;
+                            }
+                            if self.quirks.clip && (vx + row_x >= width || vy + row_y >= height) {
+                                continue;
+                            }
+                            if self.screen.flip((vx + row_x) % width, (vy + row_y) % height, plane) {
+                                self.registers.v[0xF] = 1;
+                            }
                         }
                     }
+                    index += rows * cols / 8;
                 }
 
+                self.vblank_wait = self.quirks.display_wait;
                 self.registers.step();
             },
-            Instruction::AddI => {
+            Instruction::AddI(x) => {
                 // add x to I
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
                 let vx = self.registers.v[x as usize];
-                self.registers.i += vx as u16;
-                if self.registers.i > 0xFFF { // undocumented feature
-                    self.registers.v[0xF] = 1;
-                } else {
-                    self.registers.v[0xF] = 0;
+                self.registers.i = self.registers.i.wrapping_add(vx as u16);
+                // undocumented feature, except with XO-CHIP's 64 KiB where I is allowed past 0xFFF
+                if !self.quirks.xo_chip {
+                    self.registers.v[0xF] = (self.registers.i > 0xFFF) as u8;
                 }
                 self.registers.step();
             },
-            Instruction::AddX => {
+            Instruction::AddX { x, byte } => {
                 // add byte to Vx
-                let x = self.instructions.parse_nibble(1, instr);
-                let byte = self.instructions.parse_last(instr);
-                self.print_debug_info(instruction, x as u16, byte as u16, 0);
-
                 let vx = self.registers.v[x as usize];
-                let mut r: u16 = vx as u16 + byte as u16;
-                if r > 255 {
-                    r -= 256;
-                }
-                self.registers.v[x as usize] = r as u8;
+                self.registers.v[x as usize] = vx.wrapping_add(byte);
                 self.registers.step();
             },
-            Instruction::SeX => {
+            Instruction::SeX { x, byte } => {
                 // skip if Vx equals byte
-                let x = self.instructions.parse_nibble(1, instr);
-                let byte = self.instructions.parse_last(instr);
-                self.print_debug_info(instruction, x as u16, byte as u16, 0);
-
-                let vx = self.registers.v[x as usize];
-
-                if vx == byte {
-                    self.registers.step();
+                if self.registers.v[x as usize] == byte {
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::SeXY => {
+            Instruction::SeXY { x, y } => {
                 // skip if Vx equals Vy
-                let x = self.instructions.parse_nibble(1, instr);
-                let y = self.instructions.parse_nibble(2, instr);
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                let vx = self.registers.v[x as usize];
-                let vy = self.registers.v[y as usize];
-
-                if vx == vy {
-                    self.registers.step();
+                if self.registers.v[x as usize] == self.registers.v[y as usize] {
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::LdXK => {
+            Instruction::LdXK(x) => {
                 // wait for keypress, store in Vx
                 for i in 0..self.keyboard.keyboard.len() {
                     if self.keyboard.pressed(i as u8) {
-                        let x = self.instructions.parse_nibble(1, instr);
-                        self.print_debug_info(instruction, x as u16, 0, 0); // todo: move this out of the if
-
                         self.registers.v[x as usize] = i as u8;
                         self.registers.step();
+                        break;
                     }
                 }
             },
-            Instruction::CLS => {
+            Instruction::Cls => {
                 // clear the screen
-                self.print_debug_info(instruction, 0, 0, 0);
-
-                self.screen.screen = [[0; 32]; 64];
+                self.screen.clear();
                 self.registers.step();
             },
-            Instruction::RET => {
+            Instruction::Ret => {
                 // return from subroutine
-                self.print_debug_info(instruction, 0, 0, 0);
-
+                if self.registers.sp == 0 {
+                    return Err(Rip8Error::StackUnderflow { pc: self.registers.pc, opcode: instr });
+                }
                 self.registers.sp -= 1;
                 let sp = self.registers.sp as usize;
                 let addr = self.registers.stack[sp];
                 self.registers.jump(addr);
                 self.registers.step();
             },
-            Instruction::CALL => {
+            Instruction::Call(addr) => {
                 // call subroutine
-                let addr = self.instructions.parse_address(instr);
-                self.print_debug_info(instruction, addr, 0, 0);
-
-                self.registers.stack.insert(self.registers.sp as usize, self.registers.pc);
+                let sp = self.registers.sp as usize;
+                if sp >= STACK_SIZE {
+                    return Err(Rip8Error::StackOverflow { pc: self.registers.pc, opcode: instr });
+                }
+                self.registers.stack[sp] = self.registers.pc;
                 self.registers.sp += 1;
                 self.registers.jump(addr);
             },
-            Instruction::LdXY => {
+            Instruction::LdXY { x, y } => {
                 // load value of Vy into Vx
-                let x = self.instructions.parse_nibble(1, instr);
-                let y = self.instructions.parse_nibble(2, instr);
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                let vy = self.registers.v[y as usize];
-                self.registers.v[x as usize] = vy;
+                self.registers.v[x as usize] = self.registers.v[y as usize];
                 self.registers.step();
             },
-            Instruction::SHR => {
-                // shift Vx right, bit 0 into VF
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                self.registers.v[0xF] = x & 0x1;
-                self.registers.v[x as usize] >>= 1;
+            Instruction::Shr { x, y } => {
+                // shift Vx (or Vy) right into Vx, bit 0 into VF
+                let source = self.shift_source(x, y);
+                self.registers.v[x as usize] = source >> 1;
+                self.registers.v[0xF] = source & 0x1;
+                self.registers.step();
+            },
+            Instruction::Shl { x, y } => {
+                // shift Vx (or Vy) left into Vx, bit 7 into VF
+                let source = self.shift_source(x, y);
+                self.registers.v[x as usize] = source << 1;
+                self.registers.v[0xF] = source >> 7;
                 self.registers.step();
             },
-            Instruction::LdB => {
+            Instruction::LdB(x) => {
                 // Store BCD of Vx in I, I+1 and I+2
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
                 let vx = self.registers.v[x as usize];
                 let a = vx / 100;
                 let b = (vx / 10) % 10;
                 let c = (vx % 100) % 10;
                 let i = self.registers.i as usize;
-                self.ram.write(i, a);
-                self.ram.write(i + 1, b);
-                self.ram.write(i + 2, c);
+                self.write_byte(instr, i, a)?;
+                self.write_byte(instr, i + 1, b)?;
+                self.write_byte(instr, i + 2, c)?;
                 self.registers.step();
             },
-            Instruction::LdXI => {
+            Instruction::LdXI(x) => {
                 // for 0..x => copy I+x to Vx
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
                 let index = self.registers.i as usize;
-                for i in 0..(x + 1) as usize {
-                    let byte = self.ram.read_byte(index + i);
+                for i in 0..=x as usize {
+                    let byte = self.read_byte(instr, index + i)?;
                     self.registers.v[i] = byte;
                 }
+                self.increment_i(x);
                 self.registers.step();
             },
-            Instruction::LdF => {
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                self.registers.i = (self.registers.v[x as usize] * 0x05) as u16;
+            Instruction::LdF(x) => {
+                // point I at the font sprite for the digit in Vx, every sprite is 5 bytes
+                let digit = (self.registers.v[x as usize] & 0xF) as u16;
+                self.registers.i = digit * 5;
                 self.registers.step();
             },
-            Instruction::RND => {
+            Instruction::JpV0(addr) => {
+                // jump to address + V0, or to xnn + Vx
+                let offset = if self.quirks.jump_vx {
+                    self.registers.v[(addr >> 8) as usize]
+                } else {
+                    self.registers.v[0]
+                };
+                self.registers.jump(addr + offset as u16);
+            },
+            Instruction::Rnd { x, byte } => {
                 // generate random number between 0 and 255 and AND it with the last byte, store in x
-                let x = self.instructions.parse_nibble(1, instr);
-                let byte = self.instructions.parse_last(instr);
-                self.print_debug_info(instruction, x as u16, byte as u16, 0);
-
-                let rand = rand::thread_rng().gen_range(0, 255) as u8;
+                let rand = self.random.next_byte();
                 self.registers.v[x as usize] = rand & byte;
                 self.registers.step();
             },
-            Instruction::AddXY => {
+            Instruction::AddXY { x, y } => {
                 // Vx + Vy => Vx. Set carry if greater than 255 (8 bits)
-                let x = self.instructions.parse_nibble(1, instr);
-                let y = self.instructions.parse_nibble(2, instr);
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                let vx = self.registers.v[x as usize] as u16;
-                let vy = self.registers.v[y as usize] as u16;
-                let mut r = vx + vy;
-                if r > 255 {
-                    self.registers.v[0xF] = 1;
-                    r -= 256;
-                } else {
-                    self.registers.v[0xF] = 0;
-                }
-                self.registers.v[x as usize] = r as u8;
+                let (r, carry) = self.registers.v[x as usize].overflowing_add(self.registers.v[y as usize]);
+                self.registers.v[x as usize] = r;
+                self.registers.v[0xF] = carry as u8;
                 self.registers.step();
             },
-            Instruction::SKP => {
-                // skip if Key x is pressed.
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                let vx = self.registers.v[x as usize];
+            Instruction::Skp(x) => {
+                // skip if Key x is pressed. Only the low nibble of Vx picks the key.
+                let vx = self.registers.v[x as usize] & 0xF;
                 if self.keyboard.pressed(vx) {
-                    self.registers.step();
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::SKNP => {
-                // skip if Key x is pressed.
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                let vx = self.registers.v[x as usize];
+            Instruction::Sknp(x) => {
+                // skip if Key x is not pressed.
+                let vx = self.registers.v[x as usize] & 0xF;
                 if !self.keyboard.pressed(vx) {
-                    self.registers.step();
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::LdDT => {
+            Instruction::LdDT(x) => {
                 // set delay timer to Vx
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                let vx = self.registers.v[x as usize];
-                let mut dt = DELAY_TIMER.lock().unwrap();
-                *dt = vx;
+                self.registers.delay_timer = self.registers.v[x as usize];
                 self.registers.step();
             },
-            Instruction::LdST => {
+            Instruction::LdST(x) => {
                 // set sound timer to Vx
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                let vx = self.registers.v[x as usize];
-                let mut st = SOUND_TIMER.lock().unwrap();
-                *st = vx;
+                self.registers.sound_timer = self.registers.v[x as usize];
                 self.registers.step();
             },
-            Instruction::LdXDT => {
+            Instruction::LdXDT(x) => {
                 // set Vx to delay timer
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
-                let dt = DELAY_TIMER.lock().unwrap();
-                self.registers.v[x as usize] = *dt;
+                self.registers.v[x as usize] = self.registers.delay_timer;
                 self.registers.step();
             },
-            Instruction::SneX => {
+            Instruction::SneX { x, byte } => {
                 // skip if Vx != byte
-                let x = self.instructions.parse_nibble(1, instr);
-                let byte = self.instructions.parse_last(instr);
-                self.print_debug_info(instruction, x as u16, byte as u16, 0);
-
-                let vx = self.registers.v[x as usize];
-                if vx != byte {
-                    self.registers.step();
+                if self.registers.v[x as usize] != byte {
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::SneXY => {
+            Instruction::SneXY { x, y } => {
                 // skip if Vx != Vy
-                let x = self.instructions.parse_nibble(1, instr);
-                let y = self.instructions.parse_nibble(2, instr);
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                let vx = self.registers.v[x as usize];
-                let vy = self.registers.v[y as usize];
-                if vx != vy {
-                    self.registers.step();
+                if self.registers.v[x as usize] != self.registers.v[y as usize] {
+                    self.skip_next();
                 }
                 self.registers.step();
             },
-            Instruction::LdIX => {
+            Instruction::LdIX(x) => {
                 // store V0-Vx in memory[i+x]
-                let x = self.instructions.parse_nibble(1, instr);
-                self.print_debug_info(instruction, x as u16, 0, 0);
-
                 let index = self.registers.i as usize;
-                for i in 0..x as usize{
-                    let vx = self.registers.v[x as usize];
-                    self.ram.write(index + i, vx);
+                for i in 0..=x as usize {
+                    let vi = self.registers.v[i];
+                    self.write_byte(instr, index + i, vi)?;
                 }
+                self.increment_i(x);
                 self.registers.step();
             },
-            Instruction::OR => {
+            Instruction::Or { x, y } => {
                 // Vx |= Vy
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let y = self.instructions.parse_nibble(2, instr) as usize;
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                self.registers.v[x] |= self.registers.v[y];
+                self.registers.v[x as usize] |= self.registers.v[y as usize];
+                self.reset_vf();
                 self.registers.step();
             },
-            Instruction::XOR => {
+            Instruction::Xor { x, y } => {
                 // Vx ^= Vy
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let y = self.instructions.parse_nibble(2, instr) as usize;
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                self.registers.v[x] ^= self.registers.v[y];
+                self.registers.v[x as usize] ^= self.registers.v[y as usize];
+                self.reset_vf();
                 self.registers.step();
             },
-            Instruction::AND => {
+            Instruction::And { x, y } => {
                 // Vx &= Vy
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let y = self.instructions.parse_nibble(2, instr) as usize;
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                self.registers.v[x] &= self.registers.v[y];
+                self.registers.v[x as usize] &= self.registers.v[y as usize];
+                self.reset_vf();
                 self.registers.step();
             },
-            Instruction::SUB => {
-                // Vx -= Vy, set VF
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let y = self.instructions.parse_nibble(2, instr) as usize;
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                if self.registers.v[x] > self.registers.v[y] {
-                    self.registers.v[0xF] = 1;
-                } else {
-                    self.registers.v[0xF] = 0;
+            Instruction::Sub { x, y } => {
+                // Vx -= Vy, VF is set if there was no borrow
+                let (r, borrow) = self.registers.v[x as usize].overflowing_sub(self.registers.v[y as usize]);
+                self.registers.v[x as usize] = r;
+                self.registers.v[0xF] = !borrow as u8;
+                self.registers.step();
+            },
+            Instruction::Subn { x, y } => {
+                // Vx = Vy - Vx, VF is set if there was no borrow
+                let (r, borrow) = self.registers.v[y as usize].overflowing_sub(self.registers.v[x as usize]);
+                self.registers.v[x as usize] = r;
+                self.registers.v[0xF] = !borrow as u8;
+                self.registers.step();
+            },
+            Instruction::Scd(n) => {
+                // scroll down n lines
+                self.screen.scroll_down(n as usize);
+                self.registers.step();
+            },
+            Instruction::Scr => {
+                // scroll right 4 pixels
+                self.screen.scroll_right(4);
+                self.registers.step();
+            },
+            Instruction::Scl => {
+                // scroll left 4 pixels
+                self.screen.scroll_left(4);
+                self.registers.step();
+            },
+            Instruction::Exit => {
+                // stop the interpreter
+                self.exited = true;
+            },
+            Instruction::Low => {
+                // 64x32
+                self.screen.set_hires(false);
+                self.registers.step();
+            },
+            Instruction::High => {
+                // 128x64
+                self.screen.set_hires(true);
+                self.registers.step();
+            },
+            Instruction::LdHF(x) => {
+                // point I at the 10 byte big font sprite for the digit in Vx
+                let digit = (self.registers.v[x as usize] & 0xF) as usize;
+                self.registers.i = (BIG_FONT_ADDRESS + digit * 10) as u16;
+                self.registers.step();
+            },
+            Instruction::LdRX(x) => {
+                // store V0-Vx in the RPL user flags
+                let count = x as usize + 1;
+                self.rpl[..count].copy_from_slice(&self.registers.v[..count]);
+                self.registers.step();
+            },
+            Instruction::LdXR(x) => {
+                // load V0-Vx from the RPL user flags
+                let count = x as usize + 1;
+                self.registers.v[..count].copy_from_slice(&self.rpl[..count]);
+                self.registers.step();
+            },
+            Instruction::Scu(n) => {
+                // scroll up n lines
+                self.screen.scroll_up(n as usize);
+                self.registers.step();
+            },
+            Instruction::SaveRange { x, y } => {
+                // store Vx-Vy at I, either direction, I stays put
+                let index = self.registers.i as usize;
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let byte = self.registers.v[register];
+                    self.write_byte(instr, index + offset, byte)?;
                 }
-                let mut r = self.registers.v[x] as i16 - self.registers.v[y] as i16;
-                if r < 0 {
-                    r = 255 - ((0 - r) * -1);
+                self.registers.step();
+            },
+            Instruction::LoadRange { x, y } => {
+                // load Vx-Vy from I, either direction, I stays put
+                let index = self.registers.i as usize;
+                for (offset, register) in register_range(x, y).enumerate() {
+                    self.registers.v[register] = self.read_byte(instr, index + offset)?;
                 }
-                //self.registers.v[x] -= self.registers.v[y];
-                self.registers.v[x] = r as u8;
                 self.registers.step();
             },
-            Instruction::SUBN => {
-                // Vy -= Vx, set VF
-                let x = self.instructions.parse_nibble(1, instr) as usize;
-                let y = self.instructions.parse_nibble(2, instr) as usize;
-                self.print_debug_info(instruction, x as u16, y as u16, 0);
-
-                if self.registers.v[y] > self.registers.v[x] {
-                    self.registers.v[0xF] = 1;
-                } else {
-                    self.registers.v[0xF] = 0;
+            Instruction::LdILong => {
+                // set I to the 16 bit address in the next word
+                let address = self.registers.pc as usize + 2;
+                self.registers.i = match self.ram.fetch(address) {
+                    Some(long) => long,
+                    None => return Err(Rip8Error::MemoryOutOfRange { pc: self.registers.pc, opcode: instr, address: address + 1 })
+                };
+                self.registers.step();
+                self.registers.step();
+            },
+            Instruction::Plane(n) => {
+                // select the planes DRW, CLS and scrolling work on
+                self.screen.select_planes(n);
+                self.registers.step();
+            },
+            Instruction::Audio => {
+                // load 16 bytes at I into the audio pattern buffer
+                let index = self.registers.i as usize;
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_byte(instr, index + offset)?;
                 }
-                self.registers.v[y] -= self.registers.v[x];
+                self.audio_pattern = Some(pattern);
                 self.registers.step();
             },
-            _ =>  {
-                println!("Unknown instruction: 0x{:X}", instr);
-                process::exit(0);
+            Instruction::Pitch(x) => {
+                // set the audio pattern playback rate
+                self.pitch = self.registers.v[x as usize];
+                self.registers.step();
+            },
+            Instruction::Unknown(_) => {
+                return Err(Rip8Error::InvalidOpcode { pc: self.registers.pc, opcode: instr });
             }
         }
+        Ok(())
+    }
+
+    // Steps over the next instruction, which is 4 bytes long if it is an XO-CHIP long load
+    fn skip_next(&mut self) {
+        self.registers.step();
+        if self.quirks.xo_chip && self.ram.read(self.registers.pc as usize) == Some(0xF000) {
+            self.registers.step();
+        }
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_vy {
+            self.registers.v[y as usize]
+        } else {
+            self.registers.v[x as usize]
+        }
+    }
+
+    fn increment_i(&mut self, x: u8) {
+        let by = match self.quirks.increment_i {
+            IncrementI::None => return,
+            IncrementI::X => x as u16,
+            IncrementI::XPlusOne => x as u16 + 1
+        };
+        self.registers.i = self.registers.i.wrapping_add(by);
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers.v[0xF] = 0;
+        }
+    }
+
+    fn read_byte(&self, instr: u16, address: usize) -> Result<u8, Rip8Error> {
+        match self.ram.read_byte(address) {
+            Some(byte) => Ok(byte),
+            None => Err(Rip8Error::MemoryOutOfRange { pc: self.registers.pc, opcode: instr, address })
+        }
+    }
+
+    fn write_byte(&mut self, instr: u16, address: usize, byte: u8) -> Result<(), Rip8Error> {
+        match self.ram.write(address, byte) {
+            Some(()) => Ok(()),
+            None => Err(Rip8Error::MemoryOutOfRange { pc: self.registers.pc, opcode: instr, address })
+        }
     }
 
-    fn print_debug_info(&self, instruction: Instruction, v1: u16, v2: u16, v3: u16) {
+    fn print_debug_info(&self, instruction: &Instruction) {
         if self.debug {
-            let debug_info = self.instructions.get_debug_info(instruction, self.registers.pc, v1, v2, v3);
+            let debug_info = instructions::get_debug_info(instruction, self.registers.pc);
             println!("{}", debug_info);
         }
     }
+}
 
-    fn process_debugger(&mut self) -> bool {
-        if self.interactive || self.debug_run {
-            if self.debug_run && self.registers.pc != self.break_point {
-                return true;
-            }
-            let _ = io::stdout().write("$ ".as_bytes());
-            let _ = io::stdout().flush();
-            let mut buffer = String::new();
-            let stdin = io::stdin();
-            stdin.lock().read_line(&mut buffer).expect("Could not read line.");
-            buffer = buffer.trim_right_matches("\r\n").to_string();
-            if buffer == "regdump" {
-                println!("{:#?}", self.registers);
-                let dt = DELAY_TIMER.lock().unwrap();
-                let st = SOUND_TIMER.lock().unwrap();
-                println!("delay_timer: {:#?}", *dt);
-                println!("sound_timer: {:#?}", *st);
-                return false;
-            } else if buffer == "+input" {
-                self.keyboard.set(0);
-                return false;
-            } else if buffer == "-input" {
-                self.keyboard.unset(0);
-                return false;
-            } else if buffer == "memdump" {
-                let instr = self.ram.read(self.registers.pc as usize);
-                println!("{:X}", instr);
-                return false;
-            } else if buffer.starts_with("break ") {
-                let break_point = buffer.replace("break ", "");
-                self.break_point = u16::from_str_radix(&break_point, 16).unwrap();
-                self.debug_break = true;
-                return false;
-            } else if buffer == "break" {
-                self.debug_break = false;
-                return false;
-            } else if buffer == "run" {
-                self.debug_run = true;
-                return true;
-            } else if buffer == "help" {
-                println!("{}", "regdump: dump registers");
-                println!("{}", "memdump: dump memory");
-                println!("{}", "break <addr>: set breakpoint at address");
-                println!("{}", "break: disable breakpoint");
-                println!("{}", "run: run until breakpoint");
-                println!("{}", "+input: simulate keydown");
-                println!("{}", "-input: simulate keyup");
-                println!("{}", "help: this message");
-                println!("{}", "anything else: step into");
-                return false;
-            } else {
-                return true;
-            }
-        } else {
-            return true;
+// Register indices from x to y, counting down if y < x
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(program: &[u16]) -> Cpu {
+        cpu_with(Quirks::default(), program)
+    }
+
+    fn cpu_with(quirks: Quirks, program: &[u16]) -> Cpu {
+        let mut rom = Vec::new();
+        for opcode in program {
+            rom.push((opcode >> 8) as u8);
+            rom.push(*opcode as u8);
+        }
+        let mut cpu = Cpu::new(Rom::from_bytes(rom), quirks, false, false);
+        cpu.load_font();
+        cpu.load_rom().unwrap();
+        cpu
+    }
+
+    fn run(cpu: &mut Cpu, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn sys_is_ignored() {
+        let mut cpu = cpu(&[0x0123]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn cls_clears_screen() {
+        let mut cpu = cpu(&[0x00E0]);
+        cpu.screen.screen[3][4] = 1;
+        run(&mut cpu, 1);
+        assert!(!cpu.screen.pixel(3, 4));
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut cpu = cpu(&[0x2204, 0x0000, 0x00EE]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x204);
+        assert_eq!(cpu.registers.sp, 1);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x202);
+        assert_eq!(cpu.registers.sp, 0);
+    }
+
+    #[test]
+    fn ret_on_empty_stack_underflows() {
+        let mut cpu = cpu(&[0x00EE]);
+        match cpu.step() {
+            Err(Rip8Error::StackUnderflow { pc: 0x200, opcode: 0x00EE }) => {},
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn call_overflows_after_sixteen_levels() {
+        let mut cpu = cpu(&[0x2200]);
+        run(&mut cpu, STACK_SIZE);
+        assert!(matches!(cpu.step(), Err(Rip8Error::StackOverflow { .. })));
+    }
+
+    #[test]
+    fn jp() {
+        let mut cpu = cpu(&[0x1ABC]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0xABC);
+    }
+
+    #[test]
+    fn se_x_byte() {
+        let mut cpu = cpu(&[0x6142, 0x3142, 0x0000, 0x3143]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x206);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x208);
+    }
+
+    #[test]
+    fn sne_x_byte() {
+        let mut cpu = cpu(&[0x6142, 0x4143, 0x0000, 0x4142]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x206);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x208);
+    }
+
+    #[test]
+    fn se_x_y() {
+        let mut cpu = cpu(&[0x6107, 0x6207, 0x5120, 0x0000, 0x5130]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.pc, 0x208);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x20A);
+    }
+
+    #[test]
+    fn ld_x_byte() {
+        let mut cpu = cpu(&[0x6A5C]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0xA], 0x5C);
+    }
+
+    #[test]
+    fn add_x_byte_wraps_without_carry() {
+        let mut cpu = cpu(&[0x61FF, 0x7102]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[1], 0x01);
+        assert_eq!(cpu.registers.v[0xF], 0);
+    }
+
+    #[test]
+    fn ld_x_y() {
+        let mut cpu = cpu(&[0x6233, 0x8120]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[1], 0x33);
+    }
+
+    #[test]
+    fn or() {
+        let mut cpu = cpu(&[0x61F0, 0x620F, 0x8121]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xFF);
+    }
+
+    #[test]
+    fn and() {
+        let mut cpu = cpu(&[0x61F3, 0x623F, 0x8122]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x33);
+    }
+
+    #[test]
+    fn xor() {
+        let mut cpu = cpu(&[0x61FF, 0x620F, 0x8123]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xF0);
+    }
+
+    #[test]
+    fn add_x_y_sets_carry() {
+        let mut cpu = cpu(&[0x61F0, 0x6220, 0x8124, 0x8124]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x10);
+        assert_eq!(cpu.registers.v[0xF], 1);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[1], 0x30);
+        assert_eq!(cpu.registers.v[0xF], 0);
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        let mut cpu = cpu(&[0x6105, 0x6203, 0x8125, 0x8125]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x02);
+        assert_eq!(cpu.registers.v[0xF], 1);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[1], 0xFF);
+        assert_eq!(cpu.registers.v[0xF], 0);
+    }
+
+    #[test]
+    fn shr_moves_bit_zero_of_vx_into_vf() {
+        let mut cpu = cpu(&[0x6205, 0x8206]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[2], 0x02);
+        assert_eq!(cpu.registers.v[0xF], 1);
+    }
+
+    #[test]
+    fn subn_writes_vx() {
+        let mut cpu = cpu(&[0x6103, 0x6205, 0x8127, 0x6106, 0x8127]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x02);
+        assert_eq!(cpu.registers.v[2], 0x05);
+        assert_eq!(cpu.registers.v[0xF], 1);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[1], 0xFF);
+        assert_eq!(cpu.registers.v[0xF], 0);
+    }
+
+    #[test]
+    fn shl_moves_bit_seven_of_vx_into_vf() {
+        let mut cpu = cpu(&[0x6381, 0x830E]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[3], 0x02);
+        assert_eq!(cpu.registers.v[0xF], 1);
+    }
+
+    #[test]
+    fn sne_x_y() {
+        let mut cpu = cpu(&[0x6101, 0x6202, 0x9120, 0x0000, 0x9110]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.pc, 0x208);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x20A);
+    }
+
+    #[test]
+    fn ld_i() {
+        let mut cpu = cpu(&[0xA123]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.i, 0x123);
+    }
+
+    #[test]
+    fn jp_v0() {
+        let mut cpu = cpu_with(Quirks::vip(), &[0x6010, 0xB300]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x310);
+    }
+
+    #[test]
+    fn rnd_is_masked() {
+        let mut cpu = cpu(&[0xC10F, 0xC200]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[1] & 0xF0, 0);
+        assert_eq!(cpu.registers.v[2], 0);
+    }
+
+    #[test]
+    fn drw_xors_and_reports_collision() {
+        // font sprite for 0 at (0, 0), drawn twice
+        let mut cpu = cpu(&[0xA000, 0xD005, 0xD005]);
+        run(&mut cpu, 2);
+        assert!(cpu.screen.pixel(0, 0));
+        assert!(!cpu.screen.pixel(1, 1));
+        assert_eq!(cpu.registers.v[0xF], 0);
+        run(&mut cpu, 1);
+        assert!(!cpu.screen.pixel(0, 0));
+        assert_eq!(cpu.registers.v[0xF], 1);
+    }
+
+    #[test]
+    fn skp() {
+        let mut cpu = cpu(&[0x6105, 0xE19E, 0x0000, 0xE19E]);
+        cpu.keyboard.set(5);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x206);
+        cpu.keyboard.unset(5);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x208);
+    }
+
+    #[test]
+    fn sknp() {
+        let mut cpu = cpu(&[0x6105, 0xE1A1, 0x0000, 0xE1A1]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x206);
+        cpu.keyboard.set(5);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x208);
+    }
+
+    #[test]
+    fn skp_uses_the_low_nibble_of_vx() {
+        let mut cpu = cpu(&[0x6020, 0xE09E, 0x0000, 0xE0A1]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x204);
+        cpu.keyboard.set(0);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.pc, 0x208);
+    }
+
+    #[test]
+    fn ld_x_dt() {
+        let mut cpu = cpu(&[0x612A, 0xF115, 0xF207]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[2], 0x2A);
+    }
+
+    #[test]
+    fn ld_x_k_waits_for_a_key() {
+        let mut cpu = cpu(&[0xF30A]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x200);
+        cpu.keyboard.set(0x9);
+        cpu.keyboard.set(0xB);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x202);
+        assert_eq!(cpu.registers.v[3], 0x9);
+    }
+
+    #[test]
+    fn ld_dt() {
+        let mut cpu = cpu(&[0x612A, 0xF115]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.delay_timer, 0x2A);
+    }
+
+    #[test]
+    fn ld_st() {
+        let mut cpu = cpu(&[0x612A, 0xF118]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.sound_timer, 0x2A);
+    }
+
+    #[test]
+    fn add_i() {
+        let mut cpu = cpu(&[0xA100, 0x6120, 0xF11E]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.i, 0x120);
+    }
+
+    #[test]
+    fn ld_f_does_not_overflow() {
+        let mut cpu = cpu(&[0x61FF, 0xF129]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 0xF * 5);
+    }
+
+    #[test]
+    fn ld_b() {
+        let mut cpu = cpu(&[0x619C, 0xA300, 0xF133]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.ram.ram[0x300..0x303], [1, 5, 6]);
+    }
+
+    #[test]
+    fn ld_i_x_stores_v0_through_vx() {
+        let mut cpu = cpu(&[0x6001, 0x6102, 0x6203, 0x6304, 0xA300, 0xF255]);
+        run(&mut cpu, 6);
+        assert_eq!(cpu.ram.ram[0x300..0x304], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn ld_x_i_loads_v0_through_vx() {
+        let mut cpu = cpu(&[0xA000, 0xF165]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[0..3], [0xF0, 0x90, 0]);
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        let mut cpu = cpu(&[0x5121]);
+        match cpu.step() {
+            Err(Rip8Error::InvalidOpcode { pc: 0x200, opcode: 0x5121 }) => {},
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn default_quirks_behave_like_rip8_before_quirks() {
+        // Bnnn adds V0, Fx55 leaves I alone, the sprite wraps around to x = 0
+        let mut jumping = cpu(&[0x6002, 0x6320, 0xB206]);
+        run(&mut jumping, 3);
+        assert_eq!(jumping.registers.pc, 0x208);
+        let mut drawing = cpu(&[0xA300, 0xF255, 0x603E, 0x6100, 0xA000, 0xD011]);
+        run(&mut drawing, 2);
+        assert_eq!(drawing.registers.i, 0x300);
+        run(&mut drawing, 4);
+        assert!(drawing.screen.pixel(0, 0));
+        assert_ne!(Quirks::default(), Quirks::chip48());
+        assert_ne!(Quirks::chip48(), Quirks::schip());
+    }
+
+    #[test]
+    fn quirk_shift_vy() {
+        let mut cpu = cpu_with(Quirks::vip(), &[0x6104, 0x6203, 0x8126]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x01);
+        assert_eq!(cpu.registers.v[0xF], 1);
+    }
+
+    #[test]
+    fn quirk_increment_i() {
+        let program = [0xA300, 0xF255];
+        let mut cpu = cpu_with(Quirks::vip(), &program);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 0x303);
+        let mut cpu = cpu_with(Quirks::chip48(), &program);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 0x302);
+        let mut cpu = cpu_with(Quirks::schip(), &program);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 0x300);
+    }
+
+    #[test]
+    fn quirk_jump_vx() {
+        let mut cpu = cpu_with(Quirks::schip(), &[0x6010, 0x6320, 0xB300]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.pc, 0x320);
+    }
+
+    #[test]
+    fn quirk_vf_reset() {
+        let mut cpu = cpu_with(Quirks::vip(), &[0x6F05, 0x8121]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[0xF], 0);
+        let mut cpu = cpu_with(Quirks::schip(), &[0x6F05, 0x8121]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[0xF], 5);
+    }
+
+    #[test]
+    fn quirk_clip_or_wrap() {
+        // top row of the 0 glyph (4 pixels) at x = 62
+        let program = [0x603E, 0x6100, 0xA000, 0xD011];
+        let mut cpu = cpu_with(Quirks::schip(), &program);
+        run(&mut cpu, 4);
+        assert!(cpu.screen.pixel(63, 0));
+        assert!(!cpu.screen.pixel(0, 0));
+        let mut cpu = cpu_with(Quirks::xochip(), &program);
+        run(&mut cpu, 4);
+        assert!(cpu.screen.pixel(0, 0));
+    }
+
+    #[test]
+    fn quirk_display_wait_ends_the_frame() {
+        let mut cpu = cpu_with(Quirks::vip(), &[0xD001, 0x6001]);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn quirk_overrides() {
+        let mut quirks = Quirks::vip();
+        quirks.apply_override("clip=off").unwrap();
+        assert!(!quirks.clip);
+        assert!(quirks.apply_override("clip").is_err());
+        assert!(quirks.apply_override("warp=on").is_err());
+        quirks.apply_override("memory=x").unwrap();
+        assert_eq!(quirks.increment_i, IncrementI::X);
+        assert_eq!(Quirks::from_bits(quirks.bits()), quirks);
+        quirks.apply_override("memory=off").unwrap();
+        assert_eq!(quirks.increment_i, IncrementI::None);
+        quirks.apply_override("memory=x+1").unwrap();
+        assert_eq!(quirks.increment_i, IncrementI::XPlusOne);
+        assert!(quirks.apply_override("clip=x").is_err());
+    }
+
+    #[test]
+    fn schip_resolution_switch() {
+        let mut cpu = cpu(&[0x00FF, 0x00FE]);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.screen.width(), cpu.screen.height()), (128, 64));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.screen.width(), cpu.screen.height()), (64, 32));
+    }
+
+    #[test]
+    fn schip_scroll() {
+        let mut cpu = cpu(&[0x00C2, 0x00FB, 0x00FC]);
+        cpu.screen.screen[10][10] = 1;
+        run(&mut cpu, 1);
+        assert!(cpu.screen.pixel(10, 12));
+        run(&mut cpu, 1);
+        assert!(cpu.screen.pixel(14, 12));
+        run(&mut cpu, 1);
+        assert!(cpu.screen.pixel(10, 12));
+        assert!(!cpu.screen.pixel(14, 12));
+    }
+
+    #[test]
+    fn schip_16x16_sprite() {
+        let mut cpu = cpu(&[0x00FF, 0xA300, 0xD000]);
+        for i in 0..32 {
+            cpu.ram.write(0x300 + i, 0xFF);
         }
+        run(&mut cpu, 3);
+        assert!(cpu.screen.pixel(15, 15));
+        assert!(!cpu.screen.pixel(16, 0));
+        assert!(!cpu.screen.pixel(0, 16));
+    }
+
+    #[test]
+    fn schip_big_font() {
+        let mut cpu = cpu(&[0x6108, 0xF130]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i as usize, BIG_FONT_ADDRESS + 80);
+        assert_eq!(cpu.ram.read_byte(cpu.registers.i as usize), Some(0xFF));
+    }
+
+    #[test]
+    fn schip_exit() {
+        let mut cpu = cpu(&[0x00FD]);
+        run(&mut cpu, 2);
+        assert!(cpu.exited());
+        assert_eq!(cpu.registers.pc, 0x200);
+    }
+
+    #[test]
+    fn schip_rpl_flags() {
+        let mut cpu = cpu(&[0x6007, 0x6109, 0xF175, 0x6000, 0x6100, 0xF185]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.rpl_flags()[0..3], [7, 9, 0]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[0..2], [7, 9]);
+    }
+
+    #[test]
+    fn xo_chip_opcodes_need_the_quirk() {
+        let mut cpu = cpu(&[0xF101]);
+        assert!(matches!(cpu.step(), Err(Rip8Error::InvalidOpcode { .. })));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn xo_chip_long_load_and_skip() {
+        let mut cpu = cpu_with(Quirks::xochip(), &[0xF000, 0xBEEF, 0x3000, 0xF000, 0x1234, 0x0000]);
+        assert_eq!(cpu.ram.size(), 65536);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.i, 0xBEEF);
+        assert_eq!(cpu.registers.pc, 0x204);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x20A);
+    }
+
+    #[test]
+    fn xo_chip_pc_wraps_at_the_top_of_memory() {
+        let mut cpu = cpu_with(Quirks::xochip(), &[]);
+        cpu.ram.write(0xFFFE, 0x60);
+        cpu.ram.write(0xFFFF, 0x05);
+        cpu.registers.jump(0xFFFE);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0], 5);
+        assert_eq!(cpu.registers.pc, 0x0000);
+        cpu.ram.write(0xFFFE, 0x30); // se V0, #5 skips over the top
+        cpu.registers.jump(0xFFFE);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.pc, 0x0002);
+    }
+
+    #[test]
+    fn xo_chip_add_i_leaves_vf_alone_past_0xfff() {
+        let mut cpu = cpu_with(Quirks::xochip(), &[0xAFF0, 0x6120, 0x6F07, 0xF11E]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.registers.i, 0x1010);
+        assert_eq!(cpu.registers.v[0xF], 7);
+    }
+
+    #[test]
+    fn xo_chip_register_ranges() {
+        let mut cpu = cpu_with(Quirks::xochip(), &[0x6201, 0x6302, 0x6403, 0xA300, 0x5242, 0xA400, 0x5422, 0x5023]);
+        run(&mut cpu, 5);
+        assert_eq!(cpu.ram.ram[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(cpu.registers.i, 0x300);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.ram.ram[0x400..0x403], [3, 2, 1]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0..3], [3, 2, 1]);
+    }
+
+    #[test]
+    fn xo_chip_planes() {
+        // both planes selected, plane 1 gets 0x80 and plane 2 gets 0xC0
+        let mut cpu = cpu_with(Quirks::xochip(), &[0xF301, 0xA300, 0xD001, 0xF201, 0x00E0]);
+        cpu.ram.write(0x300, 0x80);
+        cpu.ram.write(0x301, 0xC0);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.screen.colour(0, 0), 3);
+        assert_eq!(cpu.screen.colour(1, 0), 2);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.screen.colour(0, 0), 1);
+        assert_eq!(cpu.screen.colour(1, 0), 0);
+    }
+
+    #[test]
+    fn xo_chip_audio() {
+        let mut cpu = cpu_with(Quirks::xochip(), &[0xA000, 0xF002, 0x6070, 0xF03A]);
+        assert!(cpu.audio_pattern().is_none());
+        run(&mut cpu, 4);
+        assert_eq!(cpu.audio_pattern().unwrap()[0..5], FONT_SET[0..5]);
+        assert_eq!(cpu.playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn timers_count_down_per_frame() {
+        let mut cpu = cpu(&[0x6002, 0xF015, 0xF018, 0x1206]);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.delay_timer, 1);
+        assert_eq!(cpu.registers.sound_timer, 1);
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.delay_timer, 0);
+        assert_eq!(cpu.registers.sound_timer, 0);
+    }
+
+    #[test]
+    fn timers_are_per_cpu() {
+        let mut a = cpu(&[0x6009, 0xF015]);
+        let b = cpu(&[]);
+        run(&mut a, 2);
+        assert_eq!(a.registers.delay_timer, 9);
+        assert_eq!(b.registers.delay_timer, 0);
+    }
+
+    #[test]
+    fn run_frame_runs_instructions_per_frame() {
+        let mut cpu = cpu(&[0x1200]);
+        cpu.set_instructions_per_frame(11);
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.cycles(), 22);
+        assert_eq!(cpu.frames(), 2);
+    }
+
+    #[test]
+    fn rnd_is_reproducible_with_a_seed() {
+        let program = [0xC0FF, 0xC1FF, 0xC20F];
+        let mut a = cpu(&program);
+        let mut b = cpu(&program);
+        a.reseed(1234);
+        b.reseed(1234);
+        run(&mut a, 3);
+        run(&mut b, 3);
+        assert_eq!(a.registers.v, b.registers.v);
+        assert_eq!(a.seed(), b.seed());
+        assert!(a.registers.v[2] <= 0x0F);
+    }
+
+    struct Recorder {
+        key: u8,
+        frames: Vec<bool>,
+        tones: Vec<Tone>
+    }
+
+    impl InputSource for Recorder {
+        fn poll(&mut self, keyboard: &mut Keyboard) {
+            keyboard.set(self.key);
+        }
+    }
+
+    impl DisplaySink for Recorder {
+        fn present(&mut self, screen: &Screen) {
+            self.frames.push(screen.pixel(8, 0));
+        }
+    }
+
+    impl AudioSink for Recorder {
+        fn play(&mut self, tone: &Tone) {
+            self.tones.push(*tone);
+        }
+    }
+
+    #[test]
+    fn run_frame_with_drives_the_frontend() {
+        // wait for a key, draw its font glyph at x = key and beep
+        let mut cpu = cpu(&[0xF00A, 0xF029, 0xD015, 0x6105, 0xF118, 0x120A]);
+        let mut frontend = Recorder { key: 0x8, frames: Vec::new(), tones: Vec::new() };
+        cpu.run_frame_with(&mut frontend).unwrap();
+        assert_eq!(cpu.registers.v[0], 0x8);
+        assert_eq!(frontend.frames, vec![true]);
+        assert!(frontend.tones[0].playing);
+        assert_eq!(frontend.tones[0].pattern, None);
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        // draw, start the timers, then loop
+        let program = [0x00FF, 0xA000, 0xD01F, 0x6307, 0xF315, 0xF318, 0x2210, 0x0000, 0x120E];
+        let mut a = cpu_with(Quirks::vip(), &program);
+        a.reseed(99);
+        a.keyboard.set(0xB);
+        run(&mut a, 7);
+        a.run_frame().unwrap();
+        let state = a.save_state();
+
+        let mut b = cpu_with(Quirks::vip(), &program);
+        run(&mut b, 3);
+        b.load_state(&state).unwrap();
+        assert_eq!(b.save_state(), state);
+        assert_eq!(b.quirks(), &Quirks::vip());
+        assert_eq!(b.seed(), a.seed());
+        assert_eq!(b.registers.stack, a.registers.stack);
+        assert_eq!(b.registers.sound_timer, 6);
+        assert!(b.keyboard.pressed(0xB));
+        assert!(b.screen().hires());
+        assert_eq!(b.screen().screen[..], a.screen().screen[..]);
+        assert_eq!(b.cycles(), a.cycles());
+    }
+
+    #[test]
+    fn load_state_rejects_other_roms_and_garbage() {
+        let mut a = cpu(&[0x1200]);
+        let state = a.save_state();
+        let mut b = cpu(&[0x1202]);
+        assert!(matches!(b.load_state(&state), Err(Rip8Error::RomMismatch)));
+        assert!(matches!(a.load_state(&state[..state.len() - 1]), Err(Rip8Error::InvalidState(_))));
+        assert!(matches!(a.load_state(b"nope"), Err(Rip8Error::InvalidState(_))));
+        a.load_state(&state).unwrap();
+    }
+
+    #[test]
+    fn rewind_restores_previous_frames() {
+        let mut cpu = cpu(&[0x7001, 0x1200]);
+        cpu.set_rewind_frames(3);
+        for _ in 0..5 {
+            cpu.run_frame().unwrap();
+        }
+        assert_eq!(cpu.registers.v[0], 25);
+        assert!(cpu.rewind().unwrap());
+        assert_eq!(cpu.registers.v[0], 20);
+        assert_eq!(cpu.frames(), 4);
+        assert!(cpu.rewind().unwrap());
+        assert!(cpu.rewind().unwrap());
+        assert_eq!(cpu.registers.v[0], 10);
+        assert!(!cpu.rewind().unwrap());
+    }
+
+    #[test]
+    fn rewind_reports_a_bad_snapshot() {
+        let mut cpu = cpu(&[0x7001, 0x1200]);
+        cpu.set_rewind_frames(3);
+        cpu.run_frame().unwrap();
+        cpu.rewind.as_mut().unwrap().push(b"nope".to_vec());
+        assert!(matches!(cpu.rewind(), Err(Rip8Error::InvalidState(_))));
+        assert_eq!(cpu.registers.v[0], 5);
+        assert!(cpu.rewind().unwrap());
+        assert_eq!(cpu.registers.v[0], 0);
+    }
+
+    #[test]
+    fn movies_replay_deterministically() {
+        // wait for a key, then draw a random sprite at a random position forever
+        let program = [0xF00A, 0xC1FF, 0xC2FF, 0xC33F, 0xF329, 0xD125, 0x1202];
+        let mut a = cpu(&program);
+        a.reseed(5);
+        a.record_movie();
+        let mut keys = Recorder { key: 0x2, frames: Vec::new(), tones: Vec::new() };
+        for _ in 0..20 {
+            a.run_frame_with(&mut keys).unwrap();
+        }
+        let movie = a.movie().unwrap().clone();
+        assert_eq!(movie.frames.len(), 20);
+
+        let mut b = cpu(&program);
+        b.play_movie(Movie::from_bytes(&movie.to_bytes(), &b.rom.rom).unwrap()).unwrap();
+        let mut no_keys = Recorder { key: 0x0, frames: Vec::new(), tones: Vec::new() };
+        for _ in 0..20 {
+            b.run_frame_with(&mut no_keys).unwrap();
+        }
+        assert!(b.movie_finished());
+        assert_eq!(b.registers.v, a.registers.v);
+        assert_eq!(b.screen().screen[..], a.screen().screen[..]);
+    }
+}
@@ -0,0 +1,648 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use super::asm::Symbols;
+use super::cpu::Cpu;
+use super::debugger::{self, Monitor};
+use super::instruction::Instruction;
+use super::instructions;
+use super::json::{object, Json};
+use super::watch::Register;
+
+const THREAD_ID: i64 = 1; // the machine is the only thread
+const REGISTERS: i64 = 1; // variablesReference of the register scope
+
+// What the launch request asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Launch {
+    pub program: String,
+    pub stop_on_entry: bool,
+    pub symbols: Option<String>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Configuring, // until configurationDone
+    Stopped,
+    Step,
+    Continue,
+    Next { pc: u16, sp: u8 }, // stepping over a call
+    Out(u8)                   // until the stack is below this
+}
+
+// Debug adapter protocol server, so editors like VS Code can drive the machine. Messages are read
+// on their own thread so a pause can arrive while the ROM runs.
+pub struct DapServer {
+    requests: Receiver<Json>,
+    output: Box<dyn Write + Send>,
+    seq: i64,
+    launch: Option<Json>, // the launch request, answered once the ROM is loaded
+    stop_on_entry: bool,
+    symbols: Symbols,
+    source_breakpoints: Vec<(String, Vec<u16>)>,
+    function_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    mode: Mode,
+    attached: bool
+}
+
+impl DapServer {
+    // Talks over stdin and stdout, so nothing else may print to stdout. rip8 sends its messages to
+    // stderr and refuses -d in this mode.
+    pub fn stdio() -> DapServer {
+        DapServer::new(io::stdin(), io::stdout())
+    }
+
+    // Blocks until an editor connects
+    pub fn listen(address: &str) -> io::Result<DapServer> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Ok(DapServer::new(stream.try_clone()?, stream))
+    }
+
+    pub fn new<R, W>(input: R, output: W) -> DapServer
+        where R: Read + Send + 'static, W: Write + Send + 'static {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = receive(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        DapServer {
+            requests,
+            output: Box::new(output),
+            seq: 1,
+            launch: None,
+            stop_on_entry: false,
+            symbols: Symbols::default(),
+            source_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            mode: Mode::Configuring,
+            attached: true
+        }
+    }
+
+    // Answers requests until the editor says which ROM to launch. Call launched with the outcome.
+    pub fn wait_for_launch(&mut self) -> Result<Launch, String> {
+        while let Ok(request) = self.requests.recv() {
+            match request.get("command").as_str() {
+                Some("initialize") => {
+                    self.respond(&request, Ok(capabilities()));
+                    self.event("initialized", Json::Null);
+                },
+                Some("launch") => {
+                    let arguments = request.get("arguments");
+                    let launch = Launch {
+                        program: arguments.get("program").as_str().unwrap_or("").to_string(),
+                        stop_on_entry: arguments.get("stopOnEntry").as_bool().unwrap_or(false),
+                        symbols: arguments.get("symbols").as_str().map(str::to_string)
+                    };
+                    self.stop_on_entry = launch.stop_on_entry;
+                    self.launch = Some(request);
+                    return Ok(launch);
+                },
+                Some("disconnect") => {
+                    self.respond(&request, Ok(Json::Null));
+                    break;
+                },
+                // breakpoints and the like have to wait for the machine
+                _ => self.respond(&request, Err("launch first".to_string()))
+            }
+        }
+        Err("the editor went away before launching anything".to_string())
+    }
+
+    pub fn launched(&mut self, result: Result<(), String>, symbols: Symbols) {
+        self.symbols = symbols;
+        if let Some(request) = self.launch.take() {
+            self.respond(&request, result.map(|_| Json::Null));
+        }
+    }
+
+    fn send(&mut self, mut message: Vec<(&str, Json)>) {
+        message.insert(0, ("seq", self.seq.into()));
+        self.seq += 1;
+        let body = object(message).to_string();
+        let sent = write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| self.output.flush());
+        if sent.is_err() {
+            self.attached = false;
+        }
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) {
+        let request_seq = request.get("seq").clone();
+        let command = request.get("command").clone();
+        let mut response = vec![("type", "response".into()), ("request_seq", request_seq), ("command", command)];
+        match body {
+            Ok(body) => {
+                response.push(("success", true.into()));
+                if body != Json::Null {
+                    response.push(("body", body));
+                }
+            },
+            Err(message) => {
+                response.push(("success", false.into()));
+                response.push(("message", message.into()));
+            }
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        let mut message = vec![("type", "event".into()), ("event", event.into())];
+        if body != Json::Null {
+            message.push(("body", body));
+        }
+        self.send(message);
+    }
+
+    fn stop(&mut self, reason: &str) {
+        self.mode = Mode::Stopped;
+        self.event("stopped", object(vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into())
+        ]));
+    }
+
+    fn at_breakpoint(&self, pc: u16) -> bool {
+        self.source_breakpoints.iter().any(|(_, addresses)| addresses.contains(&pc)) ||
+            self.function_breakpoints.contains(&pc) ||
+            self.instruction_breakpoints.contains(&pc)
+    }
+
+    // Returns why execution should stop right after answering, if it should
+    fn handle(&mut self, cpu: &mut Cpu, request: &Json) -> Option<&'static str> {
+        let command = request.get("command").as_str().unwrap_or("");
+        let arguments = request.get("arguments");
+        let mut stop = None;
+        let body = match command {
+            "initialize" => Ok(capabilities()),
+            "setBreakpoints" => {
+                let path = arguments.get("source").get("path").as_str().unwrap_or("").to_string();
+                let mut addresses = Vec::new();
+                let mut verified = Vec::new();
+                for breakpoint in arguments.get("breakpoints").as_array() {
+                    let line = breakpoint.get("line").as_i64().unwrap_or(0);
+                    let address = self.symbols.address_for(&path, line as usize);
+                    addresses.extend(address);
+                    verified.push(breakpoint_body(address, "no code on this line, or no symbol file"));
+                }
+                self.source_breakpoints.retain(|breakpoints| breakpoints.0 != path);
+                self.source_breakpoints.push((path, addresses));
+                Ok(object(vec![("breakpoints", verified.into())]))
+            },
+            "setFunctionBreakpoints" => {
+                // labels from the symbol file, or plain addresses
+                let mut verified = Vec::new();
+                self.function_breakpoints.clear();
+                for breakpoint in arguments.get("breakpoints").as_array() {
+                    let name = breakpoint.get("name").as_str().unwrap_or("");
+                    let address = self.symbols.label(name).or_else(|| parse_address(name));
+                    self.function_breakpoints.extend(address);
+                    verified.push(breakpoint_body(address, "unknown label"));
+                }
+                Ok(object(vec![("breakpoints", verified.into())]))
+            },
+            "setInstructionBreakpoints" => {
+                let mut verified = Vec::new();
+                self.instruction_breakpoints.clear();
+                for breakpoint in arguments.get("breakpoints").as_array() {
+                    let address = breakpoint.get("instructionReference").as_str().and_then(parse_address)
+                        .map(|address| address.wrapping_add(breakpoint.get("offset").as_i64().unwrap_or(0) as u16));
+                    self.instruction_breakpoints.extend(address);
+                    verified.push(breakpoint_body(address, "invalid address"));
+                }
+                Ok(object(vec![("breakpoints", verified.into())]))
+            },
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    stop = Some("entry");
+                } else {
+                    self.mode = Mode::Continue;
+                }
+                Ok(Json::Null)
+            },
+            "threads" => Ok(object(vec![
+                ("threads", vec![object(vec![("id", THREAD_ID.into()), ("name", "chip-8".into())])].into())
+            ])),
+            "stackTrace" => {
+                // the current instruction, then every call site still on the stack
+                let registers = cpu.registers();
+                let mut frames = vec![self.frame(0, registers.pc)];
+                for (depth, &call) in registers.stack[..registers.sp as usize].iter().rev().enumerate() {
+                    frames.push(self.frame(depth as i64 + 1, call));
+                }
+                let total = frames.len() as i64;
+                Ok(object(vec![("stackFrames", frames.into()), ("totalFrames", total.into())]))
+            },
+            "scopes" => Ok(object(vec![("scopes", vec![object(vec![
+                ("name", "Registers".into()),
+                ("presentationHint", "registers".into()),
+                ("variablesReference", REGISTERS.into()),
+                ("expensive", false.into())
+            ])].into())])),
+            "variables" => Ok(object(vec![("variables", variables(cpu).into())])),
+            "setVariable" => {
+                let name = arguments.get("name").as_str().unwrap_or("");
+                let value = arguments.get("value").as_str().unwrap_or("");
+                Register::parse(name)
+                    .and_then(|register| {
+                        let number = parse_number(value).ok_or(format!("invalid value {}", value))?;
+                        debugger::set(cpu, register, number)?;
+                        Ok(register)
+                    })
+                    .map(|register| object(vec![("value", show(register, cpu).into())]))
+            },
+            "readMemory" => {
+                let ram = &cpu.ram().ram;
+                match arguments.get("memoryReference").as_str().and_then(parse_address) {
+                    Some(base) => {
+                        let start = (base as i64).saturating_add(arguments.get("offset").as_i64().unwrap_or(0)).max(0) as usize;
+                        let count = arguments.get("count").as_i64().unwrap_or(0).max(0) as usize;
+                        // anything past the end of memory is unreadable, however far past
+                        let stop = start.saturating_add(count);
+                        let end = stop.min(ram.len());
+                        let data = ram.get(start..end).unwrap_or(&[]);
+                        Ok(object(vec![
+                            ("address", format!("0x{:x}", start).into()),
+                            ("data", base64(data).into()),
+                            ("unreadableBytes", (stop.saturating_sub(end.max(start)) as i64).into())
+                        ]))
+                    },
+                    None => Err("invalid memory reference".to_string())
+                }
+            },
+            "disassemble" => match arguments.get("memoryReference").as_str().and_then(parse_address) {
+                Some(base) => {
+                    let start = base as i64 + arguments.get("offset").as_i64().unwrap_or(0) +
+                        2 * arguments.get("instructionOffset").as_i64().unwrap_or(0);
+                    let count = arguments.get("instructionCount").as_i64().unwrap_or(0).max(0);
+                    let instructions: Vec<Json> = (0..count).map(|n| disassemble(cpu, start + 2 * n)).collect();
+                    Ok(object(vec![("instructions", instructions.into())]))
+                },
+                None => Err("invalid memory reference".to_string())
+            },
+            "continue" => {
+                self.mode = Mode::Continue;
+                Ok(object(vec![("allThreadsContinued", true.into())]))
+            },
+            "next" => {
+                let registers = cpu.registers();
+                let mode = match cpu.opcode().map(instructions::decode) {
+                    Some(Instruction::Call(_)) => Mode::Next { pc: registers.pc.wrapping_add(2), sp: registers.sp },
+                    _ => Mode::Step
+                };
+                self.mode = mode;
+                Ok(Json::Null)
+            },
+            "stepIn" => {
+                self.mode = Mode::Step;
+                Ok(Json::Null)
+            },
+            "stepOut" => {
+                let sp = cpu.registers().sp;
+                self.mode = if sp == 0 { Mode::Step } else { Mode::Out(sp) };
+                Ok(Json::Null)
+            },
+            "pause" => {
+                stop = Some("pause");
+                Ok(Json::Null)
+            },
+            "terminate" => {
+                cpu.exit();
+                self.mode = Mode::Continue;
+                Ok(Json::Null)
+            },
+            "disconnect" => {
+                if arguments.get("terminateDebuggee").as_bool().unwrap_or(true) {
+                    cpu.exit();
+                }
+                self.attached = false;
+                Ok(Json::Null)
+            },
+            _ => Err(format!("{} is not supported", command))
+        };
+        self.respond(request, body);
+        stop
+    }
+
+    fn frame(&self, id: i64, address: u16) -> Json {
+        let name = match self.symbols.label_for(address) {
+            Some(label) => format!("{} (0x{:x})", label, address),
+            None => format!("0x{:x}", address)
+        };
+        let mut frame = vec![
+            ("id", id.into()),
+            ("name", name.into()),
+            ("instructionPointerReference", format!("0x{:x}", address).into()),
+            ("line", 0.into()),
+            ("column", 0.into())
+        ];
+        if let Some((file, line)) = self.symbols.line_for(address) {
+            frame[3] = ("line", (line as i64).into());
+            frame.push(("source", object(vec![("path", file.into())])));
+        }
+        object(frame)
+    }
+}
+
+impl Monitor for DapServer {
+    fn before_instruction(&mut self, cpu: &mut Cpu) {
+        if !self.attached {
+            return;
+        }
+        // requests that arrived while running
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => {
+                    if let Some(reason) = self.handle(cpu, &request) {
+                        self.stop(reason);
+                    }
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.attached = false;
+                    return;
+                }
+            }
+        }
+
+        // checked before waiting, so resuming never stops at the same instruction again
+        let registers = cpu.registers();
+        let reason = match self.mode {
+            Mode::Configuring | Mode::Stopped => None,
+            Mode::Step => Some("step"),
+            _ if self.at_breakpoint(registers.pc) => Some("breakpoint"),
+            Mode::Next { pc, sp } if registers.sp < sp || (registers.sp == sp && registers.pc == pc) => Some("step"),
+            Mode::Out(sp) if registers.sp < sp => Some("step"),
+            _ => None
+        };
+        if let Some(reason) = reason {
+            self.stop(reason);
+        }
+
+        while self.attached && (self.mode == Mode::Stopped || self.mode == Mode::Configuring) {
+            match self.requests.recv() {
+                Ok(request) => {
+                    if let Some(reason) = self.handle(cpu, &request) {
+                        self.stop(reason);
+                    }
+                },
+                Err(_) => self.attached = false
+            }
+        }
+    }
+
+    fn ended(&mut self, _cpu: &Cpu, exit_code: i32) {
+        if self.attached {
+            self.event("exited", object(vec![("exitCode", (exit_code as i64).into())]));
+            self.event("terminated", Json::Null);
+        }
+    }
+}
+
+fn capabilities() -> Json {
+    object(vec![
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsSetVariable", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsDisassembleRequest", true.into()),
+        ("supportsTerminateRequest", true.into())
+    ])
+}
+
+fn breakpoint_body(address: Option<u16>, problem: &str) -> Json {
+    match address {
+        Some(address) => object(vec![
+            ("verified", true.into()),
+            ("instructionReference", format!("0x{:x}", address).into())
+        ]),
+        None => object(vec![("verified", false.into()), ("message", problem.into())])
+    }
+}
+
+fn variables(cpu: &Cpu) -> Vec<Json> {
+    let mut registers: Vec<Register> = (0..16).map(Register::V).collect();
+    registers.extend_from_slice(&[Register::I, Register::Pc, Register::Sp, Register::Dt, Register::St]);
+    registers.iter().map(|&register| {
+        let mut variable = vec![
+            ("name", register.to_string().into()),
+            ("value", show(register, cpu).into()),
+            ("variablesReference", 0.into())
+        ];
+        // I and PC point into memory, the editor can open a memory view there
+        if let Register::I | Register::Pc = register {
+            variable.push(("memoryReference", show(register, cpu).into()));
+        }
+        object(variable)
+    }).collect()
+}
+
+fn show(register: Register, cpu: &Cpu) -> String {
+    match register {
+        Register::I | Register::Pc => format!("0x{:04x}", register.read(cpu.registers())),
+        _ => format!("0x{:02x}", register.read(cpu.registers()))
+    }
+}
+
+fn disassemble(cpu: &Cpu, address: i64) -> Json {
+    let ram = &cpu.ram().ram;
+    let reference = format!("0x{:x}", address);
+    if address < 0 || address as usize + 1 >= ram.len() {
+        return object(vec![("address", reference.into()), ("instruction", "??".into()), ("presentationHint", "invalid".into())]);
+    }
+    let opcode = (ram[address as usize] as u16) << 8 | ram[address as usize + 1] as u16;
+    object(vec![
+        ("address", reference.into()),
+        ("instructionBytes", format!("{:04x}", opcode).into()),
+        ("instruction", instructions::decode(opcode).to_string().into())
+    ])
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    match text.trim().strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.trim().parse().ok()
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Content-Length framed JSON, None once the input is gone
+fn receive<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+        let length: usize = match length {
+            Some(length) => length,
+            None => continue
+        };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        if let Ok(message) = Json::parse(&String::from_utf8_lossy(&body)) {
+            return Ok(Some(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    struct Editor {
+        stream: BufReader<TcpStream>,
+        seq: i64
+    }
+
+    impl Editor {
+        fn request(&mut self, command: &str, arguments: Json) {
+            let body = object(vec![
+                ("seq", self.seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments)
+            ]).to_string();
+            self.seq += 1;
+            write!(self.stream.get_mut(), "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+
+        fn receive(&mut self) -> Json {
+            receive(&mut self.stream).unwrap().unwrap()
+        }
+
+        // Skips events until the response to the last request shows up
+        fn response(&mut self, command: &str, arguments: Json) -> Json {
+            self.request(command, arguments);
+            loop {
+                let message = self.receive();
+                if message.get("type").as_str() == Some("response") {
+                    assert_eq!(message.get("command").as_str(), Some(command));
+                    return message;
+                }
+            }
+        }
+
+        fn stopped(&mut self) -> String {
+            loop {
+                let message = self.receive();
+                if message.get("event").as_str() == Some("stopped") {
+                    return message.get("body").get("reason").as_str().unwrap().to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+
+    #[test]
+    fn serves_an_editor() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let editor = thread::spawn(move || {
+            let mut editor = Editor { stream: BufReader::new(TcpStream::connect(address).unwrap()), seq: 1 };
+            let initialize = editor.response("initialize", object(vec![("adapterID", "rip8".into())]));
+            assert_eq!(initialize.get("body").get("supportsReadMemoryRequest").as_bool(), Some(true));
+            assert_eq!(editor.receive().get("event").as_str(), Some("initialized"));
+            let launch = editor.response("launch", object(vec![("program", "game.ch8".into()), ("stopOnEntry", true.into())]));
+            assert_eq!(launch.get("success").as_bool(), Some(true));
+            assert_eq!(editor.response("setBreakpoints", object(vec![
+                ("source", object(vec![("path", "/src/game.8s".into())])),
+                ("breakpoints", vec![object(vec![("line", 3.into())])].into())
+            ])).get("body").get("breakpoints").as_array()[0].get("verified").as_bool(), Some(true));
+            editor.response("configurationDone", Json::Null);
+            assert_eq!(editor.stopped(), "entry");
+            editor.response("continue", Json::Null);
+            assert_eq!(editor.stopped(), "breakpoint");
+
+            let stack = editor.response("stackTrace", object(vec![("threadId", 1.into())]));
+            let frames = stack.get("body").get("stackFrames").as_array();
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].get("name").as_str(), Some("sub (0x206)"));
+            assert_eq!(frames[0].get("line").as_i64(), Some(3));
+            assert_eq!(frames[1].get("instructionPointerReference").as_str(), Some("0x200"));
+
+            let variables = editor.response("variables", object(vec![("variablesReference", 1.into())]));
+            let variables = variables.get("body").get("variables").as_array();
+            assert_eq!(variables[16].get("name").as_str(), Some("i"));
+            assert_eq!(variables[16].get("value").as_str(), Some("0x0000"));
+            editor.response("setVariable", object(vec![("name", "v3".into()), ("value", "0x2a".into())]));
+
+            let memory = editor.response("readMemory", object(vec![("memoryReference", "0x200".into()), ("count", 2.into())]));
+            assert_eq!(memory.get("body").get("data").as_str(), Some("IgY="));
+            let memory = editor.response("readMemory", object(vec![("memoryReference", "0xfff".into()),
+                                                                   ("count", i64::MAX.into())]));
+            assert_eq!(memory.get("body").get("data").as_str(), Some("AA=="));
+
+            editor.response("stepOut", Json::Null);
+            assert_eq!(editor.stopped(), "step");
+            let stack = editor.response("stackTrace", Json::Null);
+            assert_eq!(stack.get("body").get("stackFrames").as_array()[0].get("name").as_str(), Some("start (0x202)"));
+            editor.response("disconnect", object(vec![("terminateDebuggee", false.into())]));
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = DapServer::new(stream.try_clone().unwrap(), stream);
+        let launch = server.wait_for_launch().unwrap();
+        assert_eq!(launch, Launch { program: "game.ch8".to_string(), stop_on_entry: true, symbols: None });
+        let symbols = Symbols::parse("0x0200 start\n0x0200 game.8s:1\n0x0202 game.8s:2\n0x0206 sub\n0x0206 game.8s:3\n");
+        server.launched(Ok(()), symbols);
+
+        // call sub, loop, sub: add V0, 1, ret
+        let rom = vec![0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+        let mut cpu = Cpu::new(Rom::from_bytes(rom), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.attach(Box::new(server));
+        for _ in 0..10 {
+            cpu.tick().unwrap();
+        }
+        editor.join().unwrap();
+        assert_eq!(cpu.registers().v[3], 0x2A);
+        assert_eq!(cpu.registers().v[0], 1);
+    }
+}
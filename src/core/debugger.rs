@@ -0,0 +1,652 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use super::cpu::Cpu;
+use super::instructions;
+use super::ram::Watch;
+use super::watch::{Before, Condition, Register, Watchpoint};
+
+const DUMP_WIDTH: usize = 16; // bytes per line of x/N
+const DEFAULT_DUMP: usize = 64;
+
+const HELP: &str = "\
+step [n], s [n]          run n instructions, an empty line steps once
+continue, c              run until a breakpoint
+finish                   run until the current subroutine returns
+back [n]                 go back n frames
+break <addr> [if <cond>] add a breakpoint, b for short
+watch <addr>[-<end>]     stop after memory is written
+rwatch <addr>[-<end>]    stop after memory is read
+awatch <addr>[-<end>]    stop after memory is read or written
+watch <reg>              stop after i, pc, sp, dt, st or vx changes, watch v for any v register
+catch collision          stop after a sprite collided
+catch key                stop when waiting for a key
+catch stack <n>          stop when more than n subroutines deep
+breakpoints, bl          list breakpoints and watchpoints
+delete [id]              delete a breakpoint or watchpoint, or all of them
+enable <id>              enable a breakpoint or watchpoint
+disable <id>             disable a breakpoint or watchpoint
+regs                     dump registers
+x/<n> <addr>             dump n bytes of memory as hex and ASCII
+set <reg> = <value>      set v0-vf, i, pc, sp, dt or st
+poke <addr> <byte>       write a byte to memory
+press <key>              hold down a key, 0-f
+release <key>            let go of a key
+help                     this message
+conditions compare registers, [addr] for a byte of memory or values with == != < <= > >=
+addresses and keys are hex, other values are decimal unless they start with 0x";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(u64),
+    Continue,
+    Finish,
+    Back(usize),
+    Break(u16, Option<Condition>),
+    Watch(Watchpoint),
+    List,
+    Delete(Option<usize>),
+    Enable(usize),
+    Disable(usize),
+    Registers,
+    Examine { address: usize, len: usize },
+    Set(Register, u16),
+    Poke(usize, u8),
+    Press(u8),
+    Release(u8),
+    Help
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(Command::Step(1))
+        };
+        let args: Vec<&str> = words.collect();
+        let arg = |i: usize| args.get(i).cloned().ok_or_else(|| format!("{} needs more arguments, see help", name));
+        let command = match name {
+            "step" | "s" => Command::Step(match args.first() {
+                Some(n) => value(n)? as u64,
+                None => 1
+            }),
+            "continue" | "c" | "run" => Command::Continue,
+            "finish" => Command::Finish,
+            "back" => Command::Back(match args.first() {
+                Some(n) => value(n)? as usize,
+                None => 1
+            }),
+            "break" | "b" => {
+                let condition = match args.get(1) {
+                    Some(&"if") => Some(Condition::parse(&args[2..].join(" "))?),
+                    Some(word) => return Err(format!("expected if instead of {}", word)),
+                    None => None
+                };
+                Command::Break(address(arg(0)?)? as u16, condition)
+            },
+            "watch" => Command::Watch(match arg(0)? {
+                "v" | "V" => Watchpoint::AnyV,
+                word => match Register::parse(word) {
+                    Ok(register) => Watchpoint::Register(register),
+                    Err(_) => Watchpoint::Memory(range(word, false, true)?)
+                }
+            }),
+            "rwatch" => Command::Watch(Watchpoint::Memory(range(arg(0)?, true, false)?)),
+            "awatch" => Command::Watch(Watchpoint::Memory(range(arg(0)?, true, true)?)),
+            "catch" => Command::Watch(match arg(0)? {
+                "collision" => Watchpoint::Collision,
+                "key" => Watchpoint::KeyWait,
+                "stack" => Watchpoint::StackDepth(value(arg(1)?)?.min(0xFF) as u8),
+                event => return Err(format!("can not catch {}, try collision, key or stack", event))
+            }),
+            "breakpoints" | "bl" => Command::List,
+            "delete" | "d" => Command::Delete(match args.first() {
+                Some(id) => Some(value(id)? as usize),
+                None => None
+            }),
+            "enable" => Command::Enable(value(arg(0)?)? as usize),
+            "disable" => Command::Disable(value(arg(0)?)? as usize),
+            "regs" | "regdump" => Command::Registers,
+            "set" => {
+                // both "set v3 = 1" and "set v3 1"
+                let register = Register::parse(arg(0)?)?;
+                let value = value(if arg(1)? == "=" { arg(2)? } else { arg(1)? })?;
+                Command::Set(register, value as u16)
+            },
+            "poke" => {
+                let value = value(arg(1)?)?;
+                if value > 0xFF {
+                    return Err(format!("{} does not fit into a byte", value));
+                }
+                Command::Poke(address(arg(0)?)?, value as u8)
+            },
+            "press" => Command::Press(key(arg(0)?)?),
+            "release" => Command::Release(key(arg(0)?)?),
+            "help" | "h" | "?" => Command::Help,
+            _ if name == "x" || name.starts_with("x/") => {
+                let len = match name.get(2..) {
+                    Some(len) if !len.is_empty() => value(len)? as usize,
+                    _ => DEFAULT_DUMP
+                };
+                Command::Examine { address: address(arg(0)?)?, len }
+            },
+            _ => return Err(format!("unknown command {}, try help", name))
+        };
+        Ok(command)
+    }
+}
+
+// Hex, with or without 0x
+fn address(word: &str) -> Result<usize, String> {
+    let digits = word.trim_start_matches("0x");
+    usize::from_str_radix(digits, 16).ok()
+        .filter(|address| *address <= 0xFFFF)
+        .ok_or_else(|| format!("invalid address {}", word))
+}
+
+// A single address or start-end, both hex
+fn range(word: &str, read: bool, write: bool) -> Result<Watch, String> {
+    let (start, end) = match word.find('-') {
+        Some(at) => (address(&word[..at])?, address(&word[at + 1..])?),
+        None => (address(word)?, address(word)?)
+    };
+    if end < start {
+        return Err(format!("invalid range {}", word));
+    }
+    Ok(Watch { start, end, read, write })
+}
+
+// Decimal, or hex with 0x
+fn value(word: &str) -> Result<u32, String> {
+    let parsed = match word.strip_prefix("0x") {
+        Some(digits) => u32::from_str_radix(digits, 16),
+        None => word.parse()
+    };
+    parsed.map_err(|_| format!("invalid number {}", word))
+}
+
+fn key(word: &str) -> Result<u8, String> {
+    u8::from_str_radix(word.trim_start_matches("0x"), 16).ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("invalid key {}, expected 0-f", word))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Break { address: u16, condition: Option<Condition> }, // stops before the instruction at address
+    Watch(Watchpoint) // stops after the instruction that triggered it
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Break { address, condition: Some(condition) } => write!(f, "0x{:x} if {}", address, condition),
+            Kind::Break { address, condition: None } => write!(f, "0x{:x}", address),
+            Kind::Watch(watchpoint) => write!(f, "{}", watchpoint)
+        }
+    }
+}
+
+// Breakpoints and watchpoints share their ids
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: Kind,
+    pub enabled: bool
+}
+
+// Gets to look at and change the machine before every instruction, see Cpu::tick
+pub trait Monitor {
+    fn before_instruction(&mut self, cpu: &mut Cpu);
+
+    // The machine is done for good, see Cpu::detach
+    fn ended(&mut self, _cpu: &Cpu, _exit_code: i32) {}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Step(u64), // instructions left to run
+    Continue,
+    Finish(u8) // stack pointer to return below
+}
+
+// The interactive debugger, asks for commands before instructions run
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    mode: Mode,
+    resumed: bool, // the instruction we stopped at runs without hitting its own breakpoint again
+    attached: bool, // false once the input is gone
+    before: Option<Before> // what watchpoints compare against
+}
+
+impl Debugger {
+    // Starts out paused at the first instruction
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            mode: Mode::Paused,
+            resumed: false,
+            attached: true,
+            before: None
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // Called before every instruction, prompts for commands until one of them resumes execution
+    pub fn before_instruction(&mut self, cpu: &mut Cpu, input: &mut dyn BufRead, output: &mut dyn Write) {
+        if !self.attached {
+            return;
+        }
+        if self.should_stop(cpu, output) {
+            self.prompt(cpu, input, output);
+            // commands may have changed the watches or swapped out memory
+            let watches = self.breakpoints.iter()
+                .filter_map(|breakpoint| match breakpoint.kind {
+                    Kind::Watch(Watchpoint::Memory(watch)) if breakpoint.enabled => Some(watch),
+                    _ => None
+                })
+                .collect();
+            cpu.ram_mut().set_watches(watches);
+        }
+        // taken after the prompt so edits made there do not count as changes
+        self.before = cpu.opcode().map(|opcode| Before {
+            registers: cpu.registers().clone(),
+            instruction: instructions::decode(opcode)
+        });
+    }
+
+    fn prompt(&mut self, cpu: &mut Cpu, input: &mut dyn BufRead, output: &mut dyn Write) {
+        self.show_location(cpu, output);
+        loop {
+            let _ = write!(output, "$ ");
+            let _ = output.flush();
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    // nobody left to type commands, just run
+                    self.attached = false;
+                    return;
+                },
+                Ok(_) => {}
+            }
+            let resumed = match Command::parse(&line) {
+                Ok(command) => self.execute(cpu, command, output),
+                Err(err) => {
+                    let _ = writeln!(output, "{}", err);
+                    false
+                }
+            };
+            if resumed {
+                self.resumed = true;
+                return;
+            }
+        }
+    }
+
+    fn should_stop(&mut self, cpu: &Cpu, output: &mut dyn Write) -> bool {
+        let resumed = self.resumed;
+        self.resumed = false;
+        let hit = cpu.ram().take_hit();
+        if let Some(before) = self.before.take() {
+            let next = cpu.opcode().map(instructions::decode);
+            for breakpoint in self.breakpoints.iter().filter(|breakpoint| breakpoint.enabled) {
+                let event = match breakpoint.kind {
+                    Kind::Watch(watchpoint) => watchpoint.check(&before, cpu, hit, next),
+                    Kind::Break { .. } => None
+                };
+                if let Some(event) = event {
+                    let _ = writeln!(output, "watchpoint {}: {}", breakpoint.id, event);
+                    self.mode = Mode::Paused;
+                    return true;
+                }
+            }
+        }
+        let pc = cpu.registers().pc;
+        if !resumed {
+            let hit = self.breakpoints.iter().find(|breakpoint| breakpoint.enabled && match breakpoint.kind {
+                Kind::Break { address, condition } => address == pc && condition.is_none_or(|condition| condition.holds(cpu)),
+                Kind::Watch(_) => false
+            });
+            if let Some(breakpoint) = hit {
+                let _ = writeln!(output, "breakpoint {} hit", breakpoint.id);
+                self.mode = Mode::Paused;
+                return true;
+            }
+        }
+        match self.mode {
+            Mode::Paused => true,
+            Mode::Step(0) => {
+                self.mode = Mode::Paused;
+                true
+            },
+            Mode::Step(n) => {
+                self.mode = Mode::Step(n - 1);
+                false
+            },
+            Mode::Continue => false,
+            Mode::Finish(sp) => {
+                if cpu.registers().sp < sp {
+                    self.mode = Mode::Paused;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn show_location(&self, cpu: &Cpu, output: &mut dyn Write) {
+        let pc = cpu.registers().pc;
+        let _ = match cpu.opcode() {
+            Some(opcode) => writeln!(output, "{}", instructions::get_debug_info(&instructions::decode(opcode), pc)),
+            None => writeln!(output, "0x{:x}: pc is outside of memory", pc)
+        };
+    }
+
+    // Returns true if execution should go on
+    pub fn execute(&mut self, cpu: &mut Cpu, command: Command, output: &mut dyn Write) -> bool {
+        let result = match command {
+            Command::Step(n) => {
+                // this instruction counts as the first one
+                self.mode = Mode::Step(n.saturating_sub(1));
+                return true;
+            },
+            Command::Continue => {
+                self.mode = Mode::Continue;
+                return true;
+            },
+            Command::Finish => {
+                if cpu.registers().sp == 0 {
+                    Err("not inside a subroutine".to_string())
+                } else {
+                    self.mode = Mode::Finish(cpu.registers().sp);
+                    return true;
+                }
+            },
+            Command::Back(frames) => {
+                let mut went_back = 0;
+                let mut failed = None;
+                while went_back < frames {
+                    match cpu.rewind() {
+                        Ok(true) => went_back += 1,
+                        Ok(false) => break,
+                        Err(err) => {
+                            failed = Some(err);
+                            break;
+                        }
+                    }
+                }
+                match failed {
+                    Some(err) => Err(format!("unable to go back: {}", err)),
+                    None if went_back == 0 => Err("no history to go back to".to_string()),
+                    None => {
+                        self.show_location(cpu, output);
+                        Ok(())
+                    }
+                }
+            },
+            Command::Break(address, condition) => {
+                let kind = Kind::Break { address, condition };
+                let id = self.add(kind);
+                writeln!(output, "breakpoint {} at {}", id, kind).map_err(|err| err.to_string())
+            },
+            Command::Watch(watchpoint) => {
+                let id = self.add(Kind::Watch(watchpoint));
+                writeln!(output, "watchpoint {} on {}", id, watchpoint).map_err(|err| err.to_string())
+            },
+            Command::List => {
+                if self.breakpoints.is_empty() {
+                    let _ = writeln!(output, "no breakpoints");
+                }
+                for breakpoint in self.breakpoints.iter() {
+                    let kind = match breakpoint.kind {
+                        Kind::Break { .. } => "breakpoint",
+                        Kind::Watch(_) => "watchpoint"
+                    };
+                    let state = if breakpoint.enabled { "" } else { " (disabled)" };
+                    let _ = writeln!(output, "{}: {} {}{}", breakpoint.id, kind, breakpoint.kind, state);
+                }
+                Ok(())
+            },
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                Ok(())
+            },
+            Command::Delete(Some(id)) => {
+                let before = self.breakpoints.len();
+                self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+                if self.breakpoints.len() == before { Err(format!("no breakpoint {}", id)) } else { Ok(()) }
+            },
+            Command::Enable(id) => self.set_enabled(id, true),
+            Command::Disable(id) => self.set_enabled(id, false),
+            Command::Registers => {
+                let _ = writeln!(output, "{:#?}", cpu.registers());
+                Ok(())
+            },
+            Command::Examine { address, len } => {
+                dump(cpu, address, len, output);
+                Ok(())
+            },
+            Command::Set(register, value) => set(cpu, register, value),
+            Command::Poke(address, byte) => {
+                // straight into memory, the debugger does not trigger watchpoints
+                cpu.ram_mut().ram.get_mut(address).map(|old| *old = byte)
+                    .ok_or_else(|| format!("0x{:x} is outside of memory", address))
+            },
+            Command::Press(key) => {
+                cpu.keyboard.set(key);
+                Ok(())
+            },
+            Command::Release(key) => {
+                cpu.keyboard.unset(key);
+                Ok(())
+            },
+            Command::Help => {
+                let _ = writeln!(output, "{}", HELP);
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            let _ = writeln!(output, "{}", err);
+        }
+        false
+    }
+
+    fn add(&mut self, kind: Kind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, kind, enabled: true });
+        id
+    }
+
+    fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<(), String> {
+        match self.breakpoints.iter_mut().find(|breakpoint| breakpoint.id == id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                Ok(())
+            },
+            None => Err(format!("no breakpoint {}", id))
+        }
+    }
+}
+
+// Talks to whoever sits at the terminal
+impl Monitor for Debugger {
+    fn before_instruction(&mut self, cpu: &mut Cpu) {
+        let stdin = io::stdin();
+        Debugger::before_instruction(self, cpu, &mut stdin.lock(), &mut io::stdout());
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+pub fn set(cpu: &mut Cpu, register: Register, value: u16) -> Result<(), String> {
+    let byte = || if value <= 0xFF { Ok(value as u8) } else { Err(format!("{} does not fit into a byte", value)) };
+    match register {
+        Register::V(x) if x <= 0xF => cpu.registers_mut().v[x as usize] = byte()?,
+        Register::V(x) => return Err(format!("there is no register v{:x}", x)),
+        Register::I => cpu.registers_mut().i = value,
+        Register::Pc => cpu.registers_mut().pc = value,
+        Register::Sp if value as usize <= super::registers::STACK_SIZE => cpu.registers_mut().sp = value as u8,
+        Register::Sp => return Err(format!("sp can not be above {}", super::registers::STACK_SIZE)),
+        Register::Dt => cpu.registers_mut().delay_timer = byte()?,
+        Register::St => cpu.registers_mut().sound_timer = byte()?
+    }
+    Ok(())
+}
+
+fn dump(cpu: &Cpu, address: usize, len: usize, output: &mut dyn Write) {
+    let ram = cpu.ram();
+    let end = (address + len).min(ram.size());
+    if address >= end {
+        let _ = writeln!(output, "0x{:x} is outside of memory", address);
+        return;
+    }
+    for line in (address..end).step_by(DUMP_WIDTH) {
+        let bytes = &ram.ram[line..(line + DUMP_WIDTH).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = bytes.iter()
+            .map(|&byte| if (0x20..0x7F).contains(&byte) { byte as char } else { '.' })
+            .collect();
+        let _ = writeln!(output, "0x{:04x}: {:<width$}  {}", line, hex.join(" "), ascii, width = DUMP_WIDTH * 3 - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(Rom::from_bytes(program.to_vec()), Quirks::default(), false, false);
+        cpu.load_font();
+        cpu.load_rom().unwrap();
+        cpu
+    }
+
+    // Runs the debugger before up to steps instructions with the given commands typed in
+    fn session(cpu: &mut Cpu, debugger: &mut Debugger, commands: &str, steps: usize) -> String {
+        let mut input = commands.as_bytes();
+        let mut output = Vec::new();
+        for _ in 0..steps {
+            debugger.before_instruction(cpu, &mut input, &mut output);
+            cpu.step().unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("\n"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 5"), Ok(Command::Step(5)));
+        assert_eq!(Command::parse("b 2a4"), Ok(Command::Break(0x2A4, None)));
+        assert_eq!(Command::parse("break 0x2a4"), Ok(Command::Break(0x2A4, None)));
+        assert_eq!(Command::parse("break 2a4 if v3 >= 2"), Ok(Command::Break(0x2A4, Some(Condition::parse("v3 >= 2").unwrap()))));
+        assert_eq!(Command::parse("watch 300-30f"),
+            Ok(Command::Watch(Watchpoint::Memory(Watch { start: 0x300, end: 0x30F, read: false, write: true }))));
+        assert_eq!(Command::parse("rwatch 300"),
+            Ok(Command::Watch(Watchpoint::Memory(Watch { start: 0x300, end: 0x300, read: true, write: false }))));
+        assert_eq!(Command::parse("watch i"), Ok(Command::Watch(Watchpoint::Register(Register::I))));
+        assert_eq!(Command::parse("watch v"), Ok(Command::Watch(Watchpoint::AnyV)));
+        assert_eq!(Command::parse("catch stack 3"), Ok(Command::Watch(Watchpoint::StackDepth(3))));
+        assert!(Command::parse("break 2a4 when v3 == 2").is_err());
+        assert!(Command::parse("watch 30f-300").is_err());
+        assert!(Command::parse("catch fire").is_err());
+        assert_eq!(Command::parse("x/32 0x200"), Ok(Command::Examine { address: 0x200, len: 32 }));
+        assert_eq!(Command::parse("set v3 = 0x10"), Ok(Command::Set(Register::V(3), 0x10)));
+        assert_eq!(Command::parse("set i 512"), Ok(Command::Set(Register::I, 512)));
+        assert_eq!(Command::parse("poke 300 255"), Ok(Command::Poke(0x300, 255)));
+        assert_eq!(Command::parse("press f"), Ok(Command::Press(0xF)));
+        assert!(Command::parse("break zz").is_err());
+        assert!(Command::parse("press 10").is_err());
+        assert!(Command::parse("poke 300 256").is_err());
+        assert!(Command::parse("set v3").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn breakpoints_stop_execution() {
+        // add V0, 1 three times, then loop
+        let mut cpu = cpu(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x06]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "b 204\nc\nregs\nc\n", 6);
+        assert!(output.contains("breakpoint 1 at 0x204"));
+        assert!(output.contains("breakpoint 1 hit"));
+        assert!(output.contains("0x204: add V0, #1"));
+        assert_eq!(cpu.registers().v[0], 3);
+    }
+
+    #[test]
+    fn step_finish_and_edits() {
+        // call a subroutine that adds twice, then loop
+        let mut cpu = cpu(&[0x22, 0x04, 0x12, 0x02, 0x70, 0x01, 0x70, 0x01, 0x00, 0xEE]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "step 2\nset v0 = 0x10\nfinish\npoke 300 41\nx/4 300\npress a\nc\n", 6);
+        assert!(output.contains("0x202: jp #202"));
+        assert!(output.contains("0x0300: 29 00 00 00"));
+        assert_eq!(cpu.registers().v[0], 0x11);
+        assert!(cpu.keyboard.pressed(0xA));
+    }
+
+    #[test]
+    fn bad_input_is_reported() {
+        let mut cpu = cpu(&[0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "break\nbreak xyz\ndelete 4\nx/4 ffff0\nc\n", 1);
+        assert!(output.contains("break needs more arguments"));
+        assert!(output.contains("invalid address xyz"));
+        assert!(output.contains("no breakpoint 4"));
+        assert!(output.contains("invalid address ffff0"));
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        // add V0, 1 in a loop
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "b 200 if v0 == 3\nc\nbl\nc\n", 10);
+        assert!(output.contains("breakpoint 1 at 0x200 if v0 == 0x3"));
+        assert!(output.contains("1: breakpoint 0x200 if v0 == 0x3"));
+        assert_eq!(output.matches("breakpoint 1 hit").count(), 1);
+        assert_eq!(cpu.registers().v[0], 5);
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_change() {
+        // I = 0x300, V0 += 1, store V0 at I, draw, loop
+        let mut cpu = cpu(&[0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "watch 300\nwatch i\nc\ndelete 2\nc\nc\nc\n", 12);
+        assert!(output.contains("watchpoint 2: i changed from 0x0 to 0x300\n0x202: add V0, #1"));
+        assert!(output.contains("watchpoint 1: write to 0x300\n0x206: drw V0, V0, #1"));
+        assert_eq!(output.matches("write to 0x300").count(), 2);
+        assert_eq!(output.matches("i changed").count(), 1);
+
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "catch collision\nc\nc\n", 12);
+        // the second draw erases the first
+        assert!(output.contains("watchpoint 1: sprite collision\n0x20a: jp #200"));
+    }
+
+    #[test]
+    fn catches_key_waits_and_deep_stacks() {
+        // wait for a key, then recurse forever
+        let mut cpu = cpu(&[0x60, 0x01, 0xF0, 0x0A, 0x22, 0x06, 0x22, 0x06]);
+        let mut debugger = Debugger::new();
+        let output = session(&mut cpu, &mut debugger, "catch key\ncatch stack 2\nc\npress 5\nc\n", 6);
+        assert!(output.contains("watchpoint 1: waiting for a key\n0x202: ld V0, K"));
+        assert!(output.contains("watchpoint 2: stack depth 3"));
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use super::instruction::Instruction;
+use super::instructions::decode;
+
+const DATA_WIDTH: usize = 8; // bytes per db line of plain data
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Data,
+    Code(Instruction),
+    Operand,     // the rest of an instruction after its first byte
+    Sprite(usize) // bytes per row, 2 for 16x16 sprites
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Label {
+    Code,
+    Data
+}
+
+// Walks a ROM from its entry point, following every branch it can see, to tell code from data.
+// Whatever is never reached is data. Sprites are found by remembering the last ld I before a drw.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    origin: u16,
+    kinds: Vec<Kind>,
+    labels: BTreeMap<u16, Label>
+}
+
+impl<'a> Disassembler<'a> {
+    // Every byte needs an address, so the ROM has to end at 0xFFFF or before
+    pub fn new(rom: &'a [u8], origin: u16) -> Result<Disassembler<'a>, String> {
+        let room = 0x10000 - origin as usize;
+        if rom.len() > room {
+            return Err(format!("{} bytes do not fit in memory from 0x{:x}, at most {} do", rom.len(), origin, room));
+        }
+        let mut disassembler = Disassembler {
+            rom,
+            origin,
+            kinds: vec![Kind::Data; rom.len()],
+            labels: BTreeMap::new()
+        };
+        disassembler.trace(origin);
+        Ok(disassembler)
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.checked_sub(self.origin)? as usize;
+        if offset < self.rom.len() { Some(offset) } else { None }
+    }
+
+    fn word(&self, address: u16) -> Option<u16> {
+        let offset = self.offset(address)?;
+        let lo = *self.rom.get(offset + 1)?;
+        Some((self.rom[offset] as u16) << 8 | lo as u16)
+    }
+
+    // ld I, long is the only instruction taking up four bytes
+    fn size(&self, address: u16) -> u16 {
+        match self.word(address).map(decode) {
+            Some(Instruction::LdILong) => 4,
+            _ => 2
+        }
+    }
+
+    fn trace(&mut self, entry: u16) {
+        let mut pending = vec![entry];
+        while let Some(mut address) = pending.pop() {
+            let mut i = None; // where I points, as far as we know
+            while let (Some(offset), Some(word)) = (self.offset(address), self.word(address)) {
+                if self.kinds[offset] != Kind::Data {
+                    break; // been here, or this would overlap another instruction
+                }
+                let instruction = decode(word);
+                let size = self.size(address);
+                if let Instruction::Unknown(_) = instruction {
+                    break;
+                }
+                if offset + size as usize > self.rom.len() {
+                    break;
+                }
+                self.kinds[offset] = Kind::Code(instruction);
+                for operand in 1..size as usize {
+                    self.kinds[offset + operand] = Kind::Operand;
+                }
+                let next = address.wrapping_add(size);
+                match instruction {
+                    Instruction::Jp(target) | Instruction::JpV0(target) => {
+                        self.label(target, Label::Code);
+                        pending.push(target);
+                        break;
+                    },
+                    Instruction::Call(target) => {
+                        self.label(target, Label::Code);
+                        pending.push(target);
+                    },
+                    Instruction::Ret | Instruction::Exit => break,
+                    Instruction::SeX { .. } | Instruction::SneX { .. } | Instruction::SeXY { .. } |
+                    Instruction::SneXY { .. } | Instruction::Skp(_) | Instruction::Sknp(_) => {
+                        pending.push(next.wrapping_add(self.size(next)));
+                    },
+                    Instruction::LdI(target) => {
+                        self.label(target, Label::Data);
+                        i = Some(target);
+                    },
+                    Instruction::LdILong => {
+                        i = self.word(address + 2);
+                        if let Some(target) = i {
+                            self.label(target, Label::Data);
+                        }
+                    },
+                    Instruction::Drw { n, .. } => {
+                        if let Some(sprite) = i {
+                            self.sprite(sprite, n);
+                        }
+                    },
+                    Instruction::AddI(_) | Instruction::LdF(_) | Instruction::LdHF(_) |
+                    Instruction::LdIX(_) | Instruction::LdXI(_) => i = None,
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+    }
+
+    // Code labels win over data labels
+    fn label(&mut self, address: u16, label: Label) {
+        if self.offset(address).is_some() && self.labels.get(&address) != Some(&Label::Code) {
+            self.labels.insert(address, label);
+        }
+    }
+
+    fn sprite(&mut self, address: u16, n: u8) {
+        let (len, width) = if n == 0 { (32, 2) } else { (n as usize, 1) };
+        if let Some(offset) = self.offset(address) {
+            for kind in self.kinds[offset..].iter_mut().take(len) {
+                if let Kind::Data | Kind::Sprite(_) = *kind {
+                    *kind = Kind::Sprite(width);
+                }
+            }
+        }
+    }
+
+    // The address of every line in the listing, with the kind of line and how many bytes it covers
+    fn lines(&self) -> Vec<(u16, Kind, usize)> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = self.origin + offset as u16;
+            let kind = self.kinds[offset];
+            let limit = match kind {
+                Kind::Code(_) => self.size(address) as usize,
+                Kind::Sprite(width) => width,
+                Kind::Data | Kind::Operand => DATA_WIDTH
+            };
+            // data lines end where the kind changes or a label has to go
+            let mut len = 1;
+            while len < limit && offset + len < self.rom.len() {
+                let same = match kind {
+                    Kind::Code(_) => true,
+                    _ => self.kinds[offset + len] == kind
+                };
+                if !same || self.labels.contains_key(&(address + len as u16)) {
+                    break;
+                }
+                len += 1;
+            }
+            lines.push((address, kind, len));
+            offset += len;
+        }
+        lines
+    }
+
+    pub fn listing(&self) -> String {
+        let lines = self.lines();
+        // labels that would end up inside an instruction are left as numbers
+        let placed: BTreeMap<u16, Label> = self.labels.iter()
+            .filter(|&(address, _)| lines.iter().any(|line| line.0 == *address))
+            .map(|(address, label)| (*address, *label))
+            .collect();
+        let name = |address: u16| match placed.get(&address) {
+            Some(&Label::Code) => format!("L{:03x}", address),
+            Some(&Label::Data) => format!("D{:03x}", address),
+            None => format!("#{:x}", address)
+        };
+
+        let mut listing = String::new();
+        for (address, kind, len) in lines {
+            if placed.contains_key(&address) {
+                listing.push_str(&format!("{}:\n", name(address)));
+            }
+            let offset = (address - self.origin) as usize;
+            let bytes = &self.rom[offset..offset + len];
+            let line = match kind {
+                Kind::Code(Instruction::LdILong) => format!("ld I, long {}", name(self.word(address + 2).unwrap_or(0))),
+                Kind::Code(instruction) => {
+                    let text = instruction.to_string();
+                    match instruction {
+                        // same text as the debug output, with the address swapped for its label
+                        Instruction::Jp(target) | Instruction::Call(target) |
+                        Instruction::LdI(target) | Instruction::JpV0(target) => {
+                            let number = format!("#{:x}", target);
+                            format!("{}{}", text.strip_suffix(number.as_str()).unwrap_or(&text), name(target))
+                        },
+                        _ => text
+                    }
+                },
+                Kind::Sprite(_) => {
+                    let bits: Vec<String> = bytes.iter().map(|byte| format!("0b{:08b}", byte)).collect();
+                    let pixels: String = bytes.iter()
+                        .flat_map(|byte| (0..8).rev().map(move |bit| if byte >> bit & 1 == 1 { '#' } else { '.' }))
+                        .collect();
+                    format!("db {} ; {}", bits.join(", "), pixels)
+                },
+                Kind::Data | Kind::Operand => {
+                    let hex: Vec<String> = bytes.iter().map(|byte| format!("#{:02x}", byte)).collect();
+                    format!("db {}", hex.join(", "))
+                }
+            };
+            listing.push_str(&format!("    {}\n", line));
+        }
+        listing
+    }
+}
+
+pub fn disassemble(rom: &[u8], origin: u16) -> Result<String, String> {
+    Ok(Disassembler::new(rom, origin)?.listing())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_code_from_sprites() {
+        let rom = [
+            0x00, 0xE0, // cls
+            0xA2, 0x08, // ld I, D208
+            0xD0, 0x12, // drw V0, V1, #2
+            0x12, 0x06, // jp L206
+            0xF0, 0x90, // sprite
+            0x12        // never reached
+        ];
+        assert_eq!(disassemble(&rom, 0x200).unwrap(), "    cls
+    ld I, D208
+    drw V0, V1, #2
+L206:
+    jp L206
+D208:
+    db 0b11110000 ; ####....
+    db 0b10010000 ; #..#....
+    db #12
+");
+    }
+
+    #[test]
+    fn follows_calls_and_skips() {
+        let rom = [
+            0x22, 0x08, // call L208
+            0x30, 0x01, // se V0, #1
+            0x12, 0x0C, // jp L20c, skipped over sometimes
+            0x00, 0xFD, // exit
+            0x60, 0x01, // ld V0, #1
+            0x00, 0xEE, // ret
+            0x12, 0x0C, // jp L20c
+            0xFF, 0xFF  // data
+        ];
+        let listing = disassemble(&rom, 0x200).unwrap();
+        assert!(listing.starts_with("    call L208\n    se V0, #1\n    jp L20c\n    exit\nL208:\n    ld V0, #1\n    ret\n"));
+        assert!(listing.ends_with("L20c:\n    jp L20c\n    db #ff, #ff\n"));
+    }
+
+    #[test]
+    fn rom_has_to_fit_below_0x10000() {
+        let rom = vec![0xFF; 0x10];
+        let listing = disassemble(&rom, 0xFFF0).unwrap();
+        assert!(listing.ends_with("    db #ff, #ff, #ff, #ff, #ff, #ff, #ff, #ff\n"), "{}", listing);
+        assert!(disassemble(&rom, 0xFFF1).is_err());
+        assert!(disassemble(&vec![0; 0x10000], 0).is_ok());
+    }
+}
@@ -0,0 +1,162 @@
+use super::screen::Screen;
+
+// One character per colour: background, plane 1, plane 2 and both planes
+const ASCII: [char; 4] = ['.', '#', '+', '*'];
+// Same colours as the window
+const PALETTE: [[u8; 3]; 4] = [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// The visible part of the screen, one line per row
+pub fn ascii(screen: &Screen) -> String {
+    let mut out = String::with_capacity((screen.width() + 1) * screen.height());
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            out.push(ASCII[screen.colour(x, y) as usize & 0x3]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// FNV-1a over the resolution and every visible pixel, stable across runs and platforms
+pub fn hash(screen: &Screen) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    };
+    feed(screen.width() as u8);
+    feed(screen.height() as u8);
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            feed(screen.colour(x, y));
+        }
+    }
+    hash
+}
+
+// An indexed colour PNG at the native resolution
+pub fn png(screen: &Screen) -> Vec<u8> {
+    let (width, height) = (screen.width(), screen.height());
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(screen.colour(x, y) & 0x3);
+        }
+    }
+    indexed_png(width, height, &PALETTE, &pixels)
+}
+
+// One palette index per pixel, row by row. The image data is stored uncompressed, nothing we
+// write is big enough for that to matter.
+pub fn indexed_png(width: usize, height: usize, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 3, 0, 0, 0]); // 8 bit depth, palette, default compression, filter and no interlacing
+
+    let mut colours = Vec::with_capacity(palette.len() * 3);
+    for colour in palette.iter() {
+        colours.extend_from_slice(colour);
+    }
+
+    let mut rows = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        rows.push(0); // no filter
+        rows.extend_from_slice(row);
+    }
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"PLTE", &colours);
+    chunk(&mut out, b"IDAT", &zlib_stored(&rows));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 }); // final block flag, type 0
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_shows_lit_pixels() {
+        let mut screen = Screen::new();
+        screen.flip(1, 0, 1);
+        let art = ascii(&screen);
+        assert_eq!(art.lines().count(), 32);
+        assert!(art.starts_with(".#...."));
+    }
+
+    #[test]
+    fn hash_changes_with_the_screen() {
+        let mut screen = Screen::new();
+        let blank = hash(&screen);
+        screen.flip(5, 5, 1);
+        assert_ne!(hash(&screen), blank);
+        screen.flip(5, 5, 1);
+        assert_eq!(hash(&screen), blank);
+    }
+
+    #[test]
+    fn png_is_well_formed() {
+        let image = png(&Screen::new());
+        assert_eq!(&image[..8], &PNG_SIGNATURE);
+        assert_eq!(&image[12..16], b"IHDR");
+        assert_eq!(&image[16..24], &[0, 0, 0, 64, 0, 0, 0, 32]);
+        // IEND always ends in the same crc
+        assert_eq!(&image[image.len() - 4..], &[0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
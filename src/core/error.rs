@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Everything that can stop the CPU. Errors raised while executing carry the PC and the raw opcode.
+#[derive(Debug)]
+pub enum Rip8Error {
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    MemoryOutOfRange { pc: u16, opcode: u16, address: usize },
+    RomIo { path: String, error: io::Error },
+    RomTooLarge { size: usize, max: usize },
+    InvalidState(&'static str),
+    InvalidMovie(&'static str),
+    RomMismatch,
+}
+
+impl Rip8Error {
+    // PC of the faulting instruction, if the error happened while executing
+    pub fn pc(&self) -> Option<u16> {
+        match *self {
+            Rip8Error::InvalidOpcode { pc, .. } |
+            Rip8Error::StackOverflow { pc, .. } |
+            Rip8Error::StackUnderflow { pc, .. } |
+            Rip8Error::MemoryOutOfRange { pc, .. } => Some(pc),
+            _ => None
+        }
+    }
+
+    // Raw opcode of the faulting instruction, if the error happened while executing
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            Rip8Error::InvalidOpcode { opcode, .. } |
+            Rip8Error::StackOverflow { opcode, .. } |
+            Rip8Error::StackUnderflow { opcode, .. } |
+            Rip8Error::MemoryOutOfRange { opcode, .. } => Some(opcode),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Rip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rip8Error::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode 0x{:04X} at 0x{:03X}", opcode, pc),
+            Rip8Error::StackOverflow { pc, opcode } =>
+                write!(f, "stack overflow at 0x{:03X} (opcode 0x{:04X})", pc, opcode),
+            Rip8Error::StackUnderflow { pc, opcode } =>
+                write!(f, "stack underflow at 0x{:03X} (opcode 0x{:04X})", pc, opcode),
+            Rip8Error::MemoryOutOfRange { pc, opcode, address } =>
+                write!(f, "memory access out of range (0x{:X}) at 0x{:03X} (opcode 0x{:04X})", address, pc, opcode),
+            Rip8Error::RomIo { ref path, ref error } =>
+                write!(f, "could not read rom {}: {}", path, error),
+            Rip8Error::RomTooLarge { size, max } =>
+                write!(f, "rom is {} bytes, but only {} fit into memory", size, max),
+            Rip8Error::InvalidState(reason) =>
+                write!(f, "invalid save state: {}", reason),
+            Rip8Error::InvalidMovie(reason) =>
+                write!(f, "invalid movie: {}", reason),
+            Rip8Error::RomMismatch =>
+                write!(f, "recorded with a different rom"),
+        }
+    }
+}
+
+impl Error for Rip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            Rip8Error::RomIo { ref error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Range;
+
+use super::cpu::Cpu;
+use super::debugger::Monitor;
+use super::registers::STACK_SIZE;
+
+const POLL_INTERVAL: u64 = 256; // instructions between checks for a ^C from gdb
+const INTERRUPT: u8 = 0x03;
+
+// Registers in the order g and p number them: V0-VF, I, PC, SP, DT, ST. Multi-byte registers are
+// big-endian like everything else on the machine.
+const REGISTER_SIZES: [usize; 21] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rip8.chip8">
+    <reg name="v0" bitsize="8"/><reg name="v1" bitsize="8"/><reg name="v2" bitsize="8"/><reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/><reg name="v5" bitsize="8"/><reg name="v6" bitsize="8"/><reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/><reg name="v9" bitsize="8"/><reg name="va" bitsize="8"/><reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/><reg name="vd" bitsize="8"/><reg name="ve" bitsize="8"/><reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Stopped,
+    Step,
+    Continue
+}
+
+// Serves the GDB remote serial protocol to a single client. Like the interactive debugger it runs
+// inside Cpu::tick, so while gdb has the machine stopped nothing else happens.
+pub struct GdbStub {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    breakpoints: Vec<u16>,
+    mode: Mode,
+    resumed: bool, // continuing from a breakpoint does not hit it again straight away
+    attached: bool,
+    since_poll: u64,
+    last: Vec<u8> // the last packet sent, for when gdb asks for it again
+}
+
+impl GdbStub {
+    // Blocks until gdb connects
+    pub fn listen(address: &str) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        GdbStub::new(stream)
+    }
+
+    // The machine starts out stopped, gdb wants to look at it first
+    pub fn new(stream: TcpStream) -> io::Result<GdbStub> {
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            breakpoints: Vec::new(),
+            mode: Mode::Stopped,
+            resumed: false,
+            attached: true,
+            since_poll: 0,
+            last: Vec::new()
+        })
+    }
+
+    fn should_stop(&mut self, cpu: &Cpu) -> Option<&'static str> {
+        let resumed = self.resumed;
+        self.resumed = false;
+        match self.mode {
+            Mode::Stopped => None,
+            Mode::Step => Some("S05"),
+            Mode::Continue if !resumed && self.breakpoints.contains(&cpu.registers().pc) => Some("S05"),
+            Mode::Continue => {
+                self.since_poll += 1;
+                if self.since_poll >= POLL_INTERVAL {
+                    self.since_poll = 0;
+                    if self.interrupted() {
+                        return Some("S02");
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    // Looks for a ^C without waiting for one
+    fn interrupted(&mut self) -> bool {
+        if self.reader.get_ref().set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = match self.reader.fill_buf() {
+            Ok(buffer) => buffer.first() == Some(&INTERRUPT),
+            Err(_) => false
+        };
+        if interrupted {
+            self.reader.consume(1);
+        }
+        let _ = self.reader.get_ref().set_nonblocking(false);
+        interrupted
+    }
+
+    // Answers packets until gdb resumes execution or goes away
+    fn serve(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            let reply = match self.handle(cpu, &packet) {
+                Some(reply) => reply,
+                None => return Ok(()) // resumed, the stop reply comes later
+            };
+            self.send(&reply)?;
+            if !self.attached {
+                return Ok(());
+            }
+        }
+        self.attached = false;
+        Ok(())
+    }
+
+    // Returns the reply, None if execution goes on
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => (0..REGISTER_SIZES.len()).map(|n| read_register(cpu, n)).collect(),
+            "G" => ok(write_registers(cpu, args)),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_SIZES.len() => read_register(cpu, n),
+                _ => "E01".to_string()
+            },
+            "P" => ok(args.split_once('=')
+                .and_then(|(n, value)| Some((usize::from_str_radix(n, 16).ok()?, from_hex(value)?)))
+                .and_then(|(n, value)| write_register(cpu, n, &value))),
+            "m" => parse_range(args)
+                .and_then(|range| cpu.ram().ram.get(range))
+                .map(to_hex)
+                .unwrap_or_else(|| "E01".to_string()),
+            "M" => ok(args.split_once(':')
+                .and_then(|(range, data)| Some((parse_range(range)?, from_hex(data)?)))
+                .and_then(|(range, data)| {
+                    let memory = cpu.ram_mut().ram.get_mut(range)?;
+                    if data.len() != memory.len() {
+                        return None;
+                    }
+                    memory.copy_from_slice(&data);
+                    Some(())
+                })),
+            "Z" | "z" => match breakpoint(args) {
+                Some(address) => {
+                    self.breakpoints.retain(|b| *b != address);
+                    if command == "Z" {
+                        self.breakpoints.push(address);
+                    }
+                    "OK".to_string()
+                },
+                None => String::new() // watchpoints are not supported
+            },
+            "c" => return self.resume(cpu, Mode::Continue, args),
+            "s" => return self.resume(cpu, Mode::Step, args),
+            "v" if args == "Cont?" => "vCont;c;s".to_string(),
+            "v" if args.starts_with("Cont;") => {
+                // one thread, the first action is all that matters
+                let mode = if args[5..].starts_with('s') { Mode::Step } else { Mode::Continue };
+                return self.resume(cpu, mode, "");
+            },
+            "q" if args.starts_with("Supported") => "PacketSize=1000;qXfer:features:read+".to_string(),
+            "q" if args.starts_with("Xfer:features:read:target.xml:") => {
+                let range = &args["Xfer:features:read:target.xml:".len()..];
+                match parse_range(range) {
+                    Some(range) => {
+                        let rest = TARGET_XML.get(range.start.min(TARGET_XML.len())..).unwrap_or("");
+                        let chunk = &rest[..range.len().min(rest.len())];
+                        format!("{}{}", if chunk.len() < rest.len() { "m" } else { "l" }, chunk)
+                    },
+                    None => "E01".to_string()
+                }
+            },
+            "q" if args == "Attached" => "1".to_string(),
+            "q" if args == "C" => "QC1".to_string(),
+            "q" if args == "fThreadInfo" => "m1".to_string(),
+            "q" if args == "sThreadInfo" => "l".to_string(),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.attached = false;
+                "OK".to_string()
+            },
+            "k" => {
+                self.attached = false;
+                cpu.exit();
+                "OK".to_string()
+            },
+            _ => String::new() // empty means unsupported
+        };
+        Some(reply)
+    }
+
+    fn resume(&mut self, cpu: &mut Cpu, mode: Mode, address: &str) -> Option<String> {
+        if let Ok(address) = u16::from_str_radix(address, 16) {
+            cpu.registers_mut().pc = address;
+        }
+        self.mode = mode;
+        self.resumed = true;
+        self.since_poll = 0;
+        None
+    }
+
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            loop {
+                if io::Read::read(&mut self.reader, &mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'$' => break,
+                    b'-' => {
+                        let last = self.last.clone();
+                        self.writer.write_all(&last)?;
+                    },
+                    _ => {} // acks, or a ^C while we are stopped anyway
+                }
+            }
+            let mut packet = Vec::new();
+            if self.reader.read_until(b'#', &mut packet)? == 0 || packet.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            io::Read::read_exact(&mut self.reader, &mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(sum(&packet)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.last = format!("${}#{:02x}", data, sum(data.as_bytes())).into_bytes();
+        self.writer.write_all(&self.last)
+    }
+}
+
+impl Monitor for GdbStub {
+    fn before_instruction(&mut self, cpu: &mut Cpu) {
+        if !self.attached {
+            return;
+        }
+        let stopped = match self.should_stop(cpu) {
+            Some(reply) => {
+                self.mode = Mode::Stopped;
+                self.send(reply).is_ok()
+            },
+            None => self.mode == Mode::Stopped
+        };
+        if stopped && self.serve(cpu).is_err() {
+            self.attached = false;
+        }
+    }
+
+    // gdb is waiting for a stop reply, W for an exit and X with SIGTRAP when the CPU failed
+    fn ended(&mut self, cpu: &Cpu, exit_code: i32) {
+        if !self.attached {
+            return;
+        }
+        self.attached = false;
+        let reply = if exit_code == 0 || cpu.exited() { format!("W{:02x}", exit_code as u8) } else { "X05".to_string() };
+        if self.send(&reply).is_ok() {
+            // wait for the ack, so gdb sees the reply before the connection goes away
+            let _ = self.reader.read(&mut [0]);
+        }
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn ok(result: Option<()>) -> String {
+    if result.is_some() { "OK".to_string() } else { "E01".to_string() }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok()).collect()
+}
+
+// addr,length in hex, None if it runs past the end of the address space
+fn parse_range(args: &str) -> Option<Range<usize>> {
+    let (address, len) = args.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    Some(address..address.checked_add(usize::from_str_radix(len, 16).ok()?)?)
+}
+
+// Software and hardware breakpoints are the same thing here: type,addr,kind
+fn breakpoint(args: &str) -> Option<u16> {
+    let mut fields = args.split(',');
+    match fields.next()? {
+        "0" | "1" => u16::from_str_radix(fields.next()?, 16).ok(),
+        _ => None
+    }
+}
+
+fn read_register(cpu: &Cpu, n: usize) -> String {
+    let registers = cpu.registers();
+    match n {
+        0..=15 => format!("{:02x}", registers.v[n]),
+        16 => format!("{:04x}", registers.i),
+        17 => format!("{:04x}", registers.pc),
+        18 => format!("{:02x}", registers.sp),
+        19 => format!("{:02x}", registers.delay_timer),
+        _ => format!("{:02x}", registers.sound_timer)
+    }
+}
+
+fn write_register(cpu: &mut Cpu, n: usize, value: &[u8]) -> Option<()> {
+    if REGISTER_SIZES.get(n) != Some(&value.len()) {
+        return None;
+    }
+    let word = value.iter().fold(0u16, |word, byte| word << 8 | *byte as u16);
+    let registers = cpu.registers_mut();
+    match n {
+        0..=15 => registers.v[n] = value[0],
+        16 => registers.i = word,
+        17 => registers.pc = word,
+        18 if (value[0] as usize) <= STACK_SIZE => registers.sp = value[0],
+        18 => return None,
+        19 => registers.delay_timer = value[0],
+        _ => registers.sound_timer = value[0]
+    }
+    Some(())
+}
+
+fn write_registers(cpu: &mut Cpu, hex: &str) -> Option<()> {
+    let bytes = from_hex(hex)?;
+    if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+        return None;
+    }
+    let mut at = 0;
+    for (n, size) in REGISTER_SIZES.iter().enumerate() {
+        write_register(cpu, n, &bytes[at..at + size])?;
+        at += size;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    // Sends a packet and returns the reply, like gdb would
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        write!(stream, "${}#{:02x}", data, sum(data.as_bytes())).unwrap();
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(reply[0], b'$');
+        assert_eq!(format!("{:02x}", sum(&reply[1..])).as_bytes(), &checksum);
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn serves_a_scripted_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut gdb = TcpStream::connect(address).unwrap();
+            assert_eq!(request(&mut gdb, "?"), "S05");
+            assert_eq!(request(&mut gdb, "g"), format!("{}0000{}{}", "00".repeat(16), "0200", "000000"));
+            assert_eq!(request(&mut gdb, "m200,4"), "70017001");
+            assert_eq!(request(&mut gdb, "M300,2:abcd"), "OK");
+            assert_eq!(request(&mut gdb, "m300,2"), "abcd");
+            assert_eq!(request(&mut gdb, "P10=0300"), "OK");
+            assert_eq!(request(&mut gdb, "p10"), "0300");
+            assert_eq!(request(&mut gdb, "Z0,204,2"), "OK");
+            assert_eq!(request(&mut gdb, "c"), "S05");
+            assert_eq!(request(&mut gdb, "p11"), "0204");
+            assert_eq!(request(&mut gdb, "p0"), "02");
+            assert_eq!(request(&mut gdb, "s"), "S05");
+            assert_eq!(request(&mut gdb, "p11"), "0206");
+            assert_eq!(request(&mut gdb, "z0,204,2"), "OK");
+            assert_eq!(request(&mut gdb, "m10000,1"), "E01");
+            assert_eq!(request(&mut gdb, "mffffffffffffffff,2"), "E01");
+            assert_eq!(request(&mut gdb, "Mffffffffffffffff,1:00"), "E01");
+            assert_eq!(request(&mut gdb, "Z2,300,1"), "");
+            assert_eq!(request(&mut gdb, "D"), "OK");
+        });
+        let (stream, _) = listener.accept().unwrap();
+
+        // add V0, 1 forever
+        let mut cpu = Cpu::new(Rom::from_bytes(vec![0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x00]), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.attach(Box::new(GdbStub::new(stream).unwrap()));
+        for _ in 0..10 {
+            cpu.tick().unwrap();
+        }
+        client.join().unwrap();
+        assert_eq!(cpu.registers().i, 0x300);
+        assert_eq!(cpu.ram().ram[0x300], 0xAB);
+    }
+
+    #[test]
+    fn reports_the_exit_to_a_waiting_gdb() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut gdb = TcpStream::connect(address).unwrap();
+            assert_eq!(request(&mut gdb, "c"), "W00");
+            assert_eq!(gdb.read(&mut [0]).unwrap(), 0);
+        });
+        let (stream, _) = listener.accept().unwrap();
+
+        // add V0, 1, exit
+        let mut cpu = Cpu::new(Rom::from_bytes(vec![0x70, 0x01, 0x00, 0xFD]), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.attach(Box::new(GdbStub::new(stream).unwrap()));
+        while !cpu.exited() {
+            cpu.tick().unwrap();
+        }
+        cpu.detach(0);
+        client.join().unwrap();
+        assert_eq!(cpu.registers().v[0], 1);
+    }
+}
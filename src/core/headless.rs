@@ -0,0 +1,169 @@
+use super::backend::{AudioSink, DisplaySink, InputSource, Tone};
+use super::cpu::Cpu;
+use super::error::Rip8Error;
+use super::keyboard::Keyboard;
+use super::screen::Screen;
+
+// A key held down for a number of frames, parsed from "<frame>:<key>[:<frames>]" with the key in hex
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub frames: u64
+}
+
+impl KeyPress {
+    pub fn parse(arg: &str) -> Result<KeyPress, String> {
+        let invalid = || format!("invalid key press {}, expected <frame>:<key>[:<frames>]", arg);
+        let parts: Vec<&str> = arg.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(invalid());
+        }
+        let frame = parts[0].parse().map_err(|_| invalid())?;
+        let key = u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?;
+        if key > 0xF {
+            return Err(invalid());
+        }
+        let frames = match parts.get(2) {
+            Some(frames) => frames.parse().map_err(|_| invalid())?,
+            None => 1
+        };
+        Ok(KeyPress { frame, key, frames })
+    }
+
+    fn held(&self, frame: u64) -> bool {
+        frame >= self.frame && frame < self.frame.saturating_add(self.frames)
+    }
+}
+
+// Stops a headless run before the instruction it matches is executed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Pc(u16),
+    Opcode(u16)
+}
+
+impl Stop {
+    pub fn matches(&self, cpu: &Cpu) -> bool {
+        match *self {
+            Stop::Pc(pc) => cpu.registers().pc == pc,
+            Stop::Opcode(opcode) => cpu.opcode() == Some(opcode)
+        }
+    }
+}
+
+// Why a headless run ended
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Frames,  // ran every frame it was asked to
+    Stopped, // hit one of the stop conditions
+    Exited   // the ROM ran 00FD
+}
+
+// A frontend without window or audio, the keypad follows a script
+pub struct Headless {
+    script: Vec<KeyPress>,
+    frame: u64
+}
+
+impl Headless {
+    pub fn new(script: Vec<KeyPress>) -> Headless {
+        Headless { script, frame: 0 }
+    }
+
+    // Runs up to frames frames, or until the ROM exits or a stop condition matches
+    pub fn run(&mut self, cpu: &mut Cpu, frames: u64, stops: &[Stop]) -> Result<Outcome, Rip8Error> {
+        for _ in 0..frames {
+            if cpu.exited() {
+                return Ok(Outcome::Exited);
+            }
+            cpu.poll_input(self);
+            if cpu.run_frame_until(|cpu| stops.iter().any(|stop| stop.matches(cpu)))? {
+                return Ok(Outcome::Stopped);
+            }
+            self.present(cpu.screen());
+        }
+        Ok(if cpu.exited() { Outcome::Exited } else { Outcome::Frames })
+    }
+}
+
+impl InputSource for Headless {
+    fn poll(&mut self, keyboard: &mut Keyboard) {
+        for key in 0..16 {
+            keyboard.unset(key);
+        }
+        for press in self.script.iter().filter(|press| press.held(self.frame)) {
+            keyboard.set(press.key);
+        }
+    }
+}
+
+impl DisplaySink for Headless {
+    // Only counts frames, the caller dumps the final screen
+    fn present(&mut self, _: &Screen) {
+        self.frame += 1;
+    }
+}
+
+impl AudioSink for Headless {
+    fn play(&mut self, _: &Tone) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(Rom::from_bytes(program.to_vec()), Quirks::default(), false, false);
+        cpu.load_font();
+        cpu.load_rom().unwrap();
+        cpu
+    }
+
+    #[test]
+    fn parses_key_presses() {
+        assert_eq!(KeyPress::parse("30:a").unwrap(), KeyPress { frame: 30, key: 0xA, frames: 1 });
+        assert_eq!(KeyPress::parse("0:F:5").unwrap(), KeyPress { frame: 0, key: 0xF, frames: 5 });
+        assert!(KeyPress::parse("30").is_err());
+        assert!(KeyPress::parse("30:10").is_err());
+    }
+
+    #[test]
+    fn runs_the_requested_frames() {
+        let mut cpu = cpu(&[0x12, 0x00]);
+        let outcome = Headless::new(Vec::new()).run(&mut cpu, 5, &[]).unwrap();
+        assert_eq!(outcome, Outcome::Frames);
+        assert_eq!(cpu.frames(), 5);
+    }
+
+    #[test]
+    fn stops_on_pc_and_opcode() {
+        // ld V0, K then jp to itself
+        let program = [0xF0, 0x0A, 0x12, 0x02];
+        let mut cpu = cpu(&program);
+        let script = vec![KeyPress::parse("3:7").unwrap()];
+        let outcome = Headless::new(script).run(&mut cpu, 100, &[Stop::Pc(0x202)]).unwrap();
+        assert_eq!(outcome, Outcome::Stopped);
+        assert_eq!(cpu.frames(), 3);
+        assert_eq!(cpu.registers().v[0], 7);
+
+        let mut cpu = self::cpu(&program);
+        let outcome = Headless::new(Vec::new()).run(&mut cpu, 100, &[Stop::Opcode(0xF00A)]).unwrap();
+        assert_eq!(outcome, Outcome::Stopped);
+        assert_eq!(cpu.cycles(), 0);
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let mut cpu = cpu(&[0x00, 0xEE]);
+        assert!(Headless::new(Vec::new()).run(&mut cpu, 1, &[]).is_err());
+    }
+
+    #[test]
+    fn reports_exit() {
+        let mut cpu = cpu(&[0x00, 0xFD]);
+        assert_eq!(Headless::new(Vec::new()).run(&mut cpu, 10, &[]).unwrap(), Outcome::Exited);
+    }
+}
@@ -1,36 +1,120 @@
-#[derive(Debug, Copy, Clone)]
+use std::fmt;
+
+// x and y are register numbers, byte/addr/n are immediates taken straight from the opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Instruction {
-    JP,
-    LdI,
-    LdV,
-    LdXK,
-    DRW,
-    AddI,
-    AddX,
-    SeX,    // swiggity swooty,
-    SeXY,   // i'm coming for that booty.
-    CLS,
-    RET,
-    CALL,
-    LdXY,
-    SHR,
-    LdB,
-    LdXI,
-    LdF,
-    RND,
-    AddXY,
-    SKP,
-    SKNP,
-    LdDT,
-    LdST,
-    LdXDT,
-    SneX,
-    SneXY,
-    LdIX,
-    AND,
-    OR,
-    XOR,
-    SUB,
-    SUBN,
-    Unknown
-}
\ No newline at end of file
+    Sys(u16),                       // 0nnn
+    Cls,                            // 00E0
+    Ret,                            // 00EE
+    Jp(u16),                        // 1nnn
+    Call(u16),                      // 2nnn
+    SeX { x: u8, byte: u8 },        // 3xkk, swiggity swooty,
+    SneX { x: u8, byte: u8 },       // 4xkk
+    SeXY { x: u8, y: u8 },          // 5xy0, i'm coming for that booty.
+    LdV { x: u8, byte: u8 },        // 6xkk
+    AddX { x: u8, byte: u8 },       // 7xkk
+    LdXY { x: u8, y: u8 },          // 8xy0
+    Or { x: u8, y: u8 },            // 8xy1
+    And { x: u8, y: u8 },           // 8xy2
+    Xor { x: u8, y: u8 },           // 8xy3
+    AddXY { x: u8, y: u8 },         // 8xy4
+    Sub { x: u8, y: u8 },           // 8xy5
+    Shr { x: u8, y: u8 },           // 8xy6
+    Subn { x: u8, y: u8 },          // 8xy7
+    Shl { x: u8, y: u8 },           // 8xyE
+    SneXY { x: u8, y: u8 },         // 9xy0
+    LdI(u16),                       // Annn
+    JpV0(u16),                      // Bnnn
+    Rnd { x: u8, byte: u8 },        // Cxkk
+    Drw { x: u8, y: u8, n: u8 },    // Dxyn
+    Skp(u8),                        // Ex9E
+    Sknp(u8),                       // ExA1
+    LdXDT(u8),                      // Fx07
+    LdXK(u8),                       // Fx0A
+    LdDT(u8),                       // Fx15
+    LdST(u8),                       // Fx18
+    AddI(u8),                       // Fx1E
+    LdF(u8),                        // Fx29
+    LdB(u8),                        // Fx33
+    LdIX(u8),                       // Fx55
+    LdXI(u8),                       // Fx65
+    // SUPER-CHIP
+    Scd(u8),                        // 00Cn
+    Scr,                            // 00FB
+    Scl,                            // 00FC
+    Exit,                           // 00FD
+    Low,                            // 00FE
+    High,                           // 00FF
+    LdHF(u8),                       // Fx30
+    LdRX(u8),                       // Fx75
+    LdXR(u8),                       // Fx85
+    // XO-CHIP
+    Scu(u8),                        // 00Dn
+    SaveRange { x: u8, y: u8 },     // 5xy2
+    LoadRange { x: u8, y: u8 },     // 5xy3
+    LdILong,                        // F000 nnnn, the address is the next word
+    Plane(u8),                      // Fn01
+    Audio,                          // F002
+    Pitch(u8),                      // Fx3A
+    Unknown(u16)                    // anything that is none of the above
+}
+
+// Same mnemonics as the -d debug output
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(addr) => write!(f, "sys #{:x}", addr),
+            Instruction::Cls => write!(f, "cls"),
+            Instruction::Ret => write!(f, "ret"),
+            Instruction::Scd(n) => write!(f, "scd #{:x}", n),
+            Instruction::Scr => write!(f, "scr"),
+            Instruction::Scl => write!(f, "scl"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::Low => write!(f, "low"),
+            Instruction::High => write!(f, "high"),
+            Instruction::Scu(n) => write!(f, "scu #{:x}", n),
+            Instruction::SaveRange { x, y } => write!(f, "ld [I], V{:x}-V{:x}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "ld V{:x}-V{:x}, [I]", x, y),
+            Instruction::LdILong => write!(f, "ld I, long"),
+            Instruction::Plane(n) => write!(f, "plane #{:x}", n),
+            Instruction::Audio => write!(f, "audio"),
+            Instruction::Pitch(x) => write!(f, "ld PITCH, V{:x}", x),
+            Instruction::Jp(addr) => write!(f, "jp #{:x}", addr),
+            Instruction::Call(addr) => write!(f, "call #{:x}", addr),
+            Instruction::SeX { x, byte } => write!(f, "se V{:x}, #{:x}", x, byte),
+            Instruction::SneX { x, byte } => write!(f, "sne V{:x}, #{:x}", x, byte),
+            Instruction::SeXY { x, y } => write!(f, "se V{:x}, V{:x}", x, y),
+            Instruction::LdV { x, byte } => write!(f, "ld V{:x}, #{:x}", x, byte),
+            Instruction::AddX { x, byte } => write!(f, "add V{:x}, #{:x}", x, byte),
+            Instruction::LdXY { x, y } => write!(f, "ld V{:x}, V{:x}", x, y),
+            Instruction::Or { x, y } => write!(f, "or V{:x}, V{:x}", x, y),
+            Instruction::And { x, y } => write!(f, "and V{:x}, V{:x}", x, y),
+            Instruction::Xor { x, y } => write!(f, "xor V{:x}, V{:x}", x, y),
+            Instruction::AddXY { x, y } => write!(f, "add V{:x}, V{:x}", x, y),
+            Instruction::Sub { x, y } => write!(f, "sub V{:x}, V{:x}", x, y),
+            Instruction::Shr { x, y } => write!(f, "shr V{:x}, V{:x}", x, y),
+            Instruction::Subn { x, y } => write!(f, "subn V{:x}, V{:x}", x, y),
+            Instruction::Shl { x, y } => write!(f, "shl V{:x}, V{:x}", x, y),
+            Instruction::SneXY { x, y } => write!(f, "sne V{:x}, V{:x}", x, y),
+            Instruction::LdI(addr) => write!(f, "ld I, #{:x}", addr),
+            Instruction::JpV0(addr) => write!(f, "jp V0, #{:x}", addr),
+            Instruction::Rnd { x, byte } => write!(f, "rnd V{:x}, #{:x}", x, byte),
+            Instruction::Drw { x, y, n } => write!(f, "drw V{:x}, V{:x}, #{:x}", x, y, n),
+            Instruction::Skp(x) => write!(f, "skp V{:x}", x),
+            Instruction::Sknp(x) => write!(f, "sknp V{:x}", x),
+            Instruction::LdXDT(x) => write!(f, "ld V{:x}, DT", x),
+            Instruction::LdXK(x) => write!(f, "ld V{:x}, K", x),
+            Instruction::LdDT(x) => write!(f, "ld DT, V{:x}", x),
+            Instruction::LdST(x) => write!(f, "ld ST, V{:x}", x),
+            Instruction::AddI(x) => write!(f, "add I, V{:x}", x),
+            Instruction::LdF(x) => write!(f, "ld F, V{:x}", x),
+            Instruction::LdB(x) => write!(f, "ld B, V{:x}", x),
+            Instruction::LdIX(x) => write!(f, "ld [I], V{:x}", x),
+            Instruction::LdXI(x) => write!(f, "ld V{:x}, [I]", x),
+            Instruction::LdHF(x) => write!(f, "ld HF, V{:x}", x),
+            Instruction::LdRX(x) => write!(f, "ld R, V{:x}", x),
+            Instruction::LdXR(x) => write!(f, "ld V{:x}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "dw #{:04x}", opcode)
+        }
+    }
+}
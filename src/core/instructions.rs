@@ -1,112 +1,218 @@
 use super::instruction::Instruction;
 
-pub struct Instructions {
+// The one place opcodes get taken apart. Decoding is strict: any bit pattern that is not an exact
+// match ends up as Unknown, which keeps encode(decode(op)) == Some(op) for every u16.
+pub fn decode(opcode: u16) -> Instruction {
+    let x = parse_nibble(1, opcode);
+    let y = parse_nibble(2, opcode);
+    let n = parse_nibble(3, opcode);
+    let byte = parse_last(opcode);
+    let addr = parse_address(opcode);
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x00FB => Instruction::Scr,
+            0x00FC => Instruction::Scl,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Low,
+            0x00FF => Instruction::High,
+            _ if opcode & 0xFFF0 == 0x00C0 => Instruction::Scd(n),
+            _ if opcode & 0xFFF0 == 0x00D0 => Instruction::Scu(n),
+            _ => Instruction::Sys(addr)
+        },
+        0x1000 => Instruction::Jp(addr),
+        0x2000 => Instruction::Call(addr),
+        0x3000 => Instruction::SeX { x, byte },
+        0x4000 => Instruction::SneX { x, byte },
+        0x5000 => match n {
+            0x0 => Instruction::SeXY { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => Instruction::Unknown(opcode)
+        },
+        0x6000 => Instruction::LdV { x, byte },
+        0x7000 => Instruction::AddX { x, byte },
+        // The CHIP8 does also have a number of opcodes starting with 8, identifiable by the last nibble.
+        0x8000 => match n {
+            0x0 => Instruction::LdXY { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddXY { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::Shr { x, y },
+            0x7 => Instruction::Subn { x, y },
+            0xE => Instruction::Shl { x, y },
+            _ => Instruction::Unknown(opcode)
+        },
+        0x9000 if n == 0 => Instruction::SneXY { x, y },
+        0xA000 => Instruction::LdI(addr),
+        0xB000 => Instruction::JpV0(addr),
+        0xC000 => Instruction::Rnd { x, byte },
+        0xD000 => Instruction::Drw { x, y, n },
+        // CHIP8 has a series of opcodes which start with F and E, the last byte makes them identifiable.
+        0xE000 => match byte {
+            0x9E => Instruction::Skp(x),
+            0xA1 => Instruction::Sknp(x),
+            _ => Instruction::Unknown(opcode)
+        },
+        0xF000 => match byte {
+            0x00 if x == 0 => Instruction::LdILong,
+            0x01 => Instruction::Plane(x),
+            0x02 if x == 0 => Instruction::Audio,
+            0x3A => Instruction::Pitch(x),
+            0x07 => Instruction::LdXDT(x),
+            0x0A => Instruction::LdXK(x),
+            0x15 => Instruction::LdDT(x),
+            0x18 => Instruction::LdST(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LdF(x),
+            0x33 => Instruction::LdB(x),
+            0x55 => Instruction::LdIX(x),
+            0x65 => Instruction::LdXI(x),
+            0x30 => Instruction::LdHF(x),
+            0x75 => Instruction::LdRX(x),
+            0x85 => Instruction::LdXR(x),
+            _ => Instruction::Unknown(opcode)
+        },
+        _ => Instruction::Unknown(opcode)
+    }
+}
 
+// None when the operands do not fit their fields, or the opcode would decode as something else,
+// like Sys(0x0E0) which is Cls
+pub fn encode(instruction: &Instruction) -> Option<u16> {
+    let opcode = match *instruction {
+        Instruction::Sys(addr) => addr & 0x0FFF,
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::Scd(n) => 0x00C0 | (n as u16 & 0xF),
+        Instruction::Scr => 0x00FB,
+        Instruction::Scl => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::Low => 0x00FE,
+        Instruction::High => 0x00FF,
+        Instruction::Scu(n) => 0x00D0 | (n as u16 & 0xF),
+        Instruction::SaveRange { x, y } => xyn(0x5000, x, y, 0x2),
+        Instruction::LoadRange { x, y } => xyn(0x5000, x, y, 0x3),
+        Instruction::LdILong => 0xF000,
+        Instruction::Plane(n) => xkk(0xF000, n, 0x01),
+        Instruction::Audio => 0xF002,
+        Instruction::Pitch(x) => xkk(0xF000, x, 0x3A),
+        Instruction::Jp(addr) => 0x1000 | addr & 0x0FFF,
+        Instruction::Call(addr) => 0x2000 | addr & 0x0FFF,
+        Instruction::SeX { x, byte } => xkk(0x3000, x, byte),
+        Instruction::SneX { x, byte } => xkk(0x4000, x, byte),
+        Instruction::SeXY { x, y } => xyn(0x5000, x, y, 0x0),
+        Instruction::LdV { x, byte } => xkk(0x6000, x, byte),
+        Instruction::AddX { x, byte } => xkk(0x7000, x, byte),
+        Instruction::LdXY { x, y } => xyn(0x8000, x, y, 0x0),
+        Instruction::Or { x, y } => xyn(0x8000, x, y, 0x1),
+        Instruction::And { x, y } => xyn(0x8000, x, y, 0x2),
+        Instruction::Xor { x, y } => xyn(0x8000, x, y, 0x3),
+        Instruction::AddXY { x, y } => xyn(0x8000, x, y, 0x4),
+        Instruction::Sub { x, y } => xyn(0x8000, x, y, 0x5),
+        Instruction::Shr { x, y } => xyn(0x8000, x, y, 0x6),
+        Instruction::Subn { x, y } => xyn(0x8000, x, y, 0x7),
+        Instruction::Shl { x, y } => xyn(0x8000, x, y, 0xE),
+        Instruction::SneXY { x, y } => xyn(0x9000, x, y, 0x0),
+        Instruction::LdI(addr) => 0xA000 | addr & 0x0FFF,
+        Instruction::JpV0(addr) => 0xB000 | addr & 0x0FFF,
+        Instruction::Rnd { x, byte } => xkk(0xC000, x, byte),
+        Instruction::Drw { x, y, n } => xyn(0xD000, x, y, n),
+        Instruction::Skp(x) => xkk(0xE000, x, 0x9E),
+        Instruction::Sknp(x) => xkk(0xE000, x, 0xA1),
+        Instruction::LdXDT(x) => xkk(0xF000, x, 0x07),
+        Instruction::LdXK(x) => xkk(0xF000, x, 0x0A),
+        Instruction::LdDT(x) => xkk(0xF000, x, 0x15),
+        Instruction::LdST(x) => xkk(0xF000, x, 0x18),
+        Instruction::AddI(x) => xkk(0xF000, x, 0x1E),
+        Instruction::LdF(x) => xkk(0xF000, x, 0x29),
+        Instruction::LdB(x) => xkk(0xF000, x, 0x33),
+        Instruction::LdIX(x) => xkk(0xF000, x, 0x55),
+        Instruction::LdXI(x) => xkk(0xF000, x, 0x65),
+        Instruction::LdHF(x) => xkk(0xF000, x, 0x30),
+        Instruction::LdRX(x) => xkk(0xF000, x, 0x75),
+        Instruction::LdXR(x) => xkk(0xF000, x, 0x85),
+        Instruction::Unknown(opcode) => opcode
+    };
+    Some(opcode).filter(|&opcode| decode(opcode) == *instruction)
 }
 
-impl Instructions {
-    pub fn new() -> Instructions {
-        Instructions {
+fn xkk(base: u16, x: u8, byte: u8) -> u16 {
+    base | ((x as u16 & 0xF) << 8) | byte as u16
+}
 
-        }
-    }
+fn xyn(base: u16, x: u8, y: u8, n: u8) -> u16 {
+    base | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n as u16 & 0xF)
+}
 
-    pub fn parse(&self, opcode: u16) -> Instruction {
-        match opcode {
-            0x1000 => Instruction::JP,
-            0xA000 => Instruction::LdI,
-            0x6000 => Instruction::LdV,
-            0xD000 => Instruction::DRW,
-            0xF01E => Instruction::AddI,
-            0x7000 => Instruction::AddX,
-            0x3000 => Instruction::SeX,
-            0x5000 => Instruction::SeXY,
-            0xF00A => Instruction::LdXK,
-            0xE0 => Instruction::CLS,
-            0xEE => Instruction::RET,
-            0x2000 => Instruction::CALL,
-            0x8000 => Instruction::LdXY,
-            0x8006 => Instruction::SHR,
-            0xF033 => Instruction::LdB,
-            0xF065 => Instruction::LdXI,
-            0xF029 => Instruction::LdF,
-            0xC000 => Instruction::RND,
-            0x8004 => Instruction::AddXY,
-            0xE09E => Instruction::SKP,
-            0xE0A1 => Instruction::SKNP,
-            0xF015 => Instruction::LdDT,
-            0xF018 => Instruction::LdST,
-            0xF007 => Instruction::LdXDT,
-            0x4000 => Instruction::SneX,
-            0x9000 => Instruction::SneXY,
-            0xF055 => Instruction::LdIX,
-            0x8001 => Instruction::OR,
-            0x8002 => Instruction::AND,
-            0x8003 => Instruction::XOR,
-            0x8005 => Instruction::SUB,
-            0x8007 => Instruction::SUBN,
-            _ => Instruction::Unknown
-        }
-    }
+pub fn parse_address(opcode: u16) -> u16 {
+    opcode & 0x0FFF
+}
+
+pub fn parse_last(opcode: u16) -> u8 {
+    (opcode & 0x00FF) as u8
+}
 
-    pub fn parse_address(&self, opcode: u16) -> u16 {
-        opcode & 0x0FFF
+pub fn parse_nibble(nibble: u8, opcode: u16) -> u8 {
+    let shift = 12 - 4 * (nibble as u16 & 0x3);
+    ((opcode >> shift) & 0xF) as u8
+}
+
+// Opcodes that only exist with the XO-CHIP extensions enabled
+pub fn is_xo_chip(instruction: &Instruction) -> bool {
+    matches!(*instruction,
+        Instruction::Scu(_) | Instruction::SaveRange { .. } | Instruction::LoadRange { .. } |
+        Instruction::LdILong | Instruction::Plane(_) | Instruction::Audio | Instruction::Pitch(_))
+}
+
+// Get instruction details
+pub fn get_debug_info(instruction: &Instruction, pc: u16) -> String {
+    format!("0x{:x}: {}", pc, instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_inverts_decode_for_every_opcode() {
+        for opcode in 0..=0xFFFFu16 {
+            assert_eq!(encode(&decode(opcode)), Some(opcode), "0x{:04X}", opcode);
+        }
     }
 
-    pub fn parse_last(&self, opcode: u16) -> u8 {
-        (opcode & 0x00FF) as u8
+    #[test]
+    fn encode_rejects_what_does_not_decode_back() {
+        assert_eq!(encode(&Instruction::LdV { x: 0xF, byte: 0x12 }), Some(0x6F12));
+        assert_eq!(encode(&Instruction::Drw { x: 1, y: 2, n: 0xF }), Some(0xD12F));
+        assert_eq!(encode(&Instruction::Sys(0x2F0)), Some(0x02F0));
+        assert_eq!(encode(&Instruction::LdV { x: 0x1F, byte: 0x12 }), None);
+        assert_eq!(encode(&Instruction::Drw { x: 1, y: 0x12, n: 5 }), None);
+        assert_eq!(encode(&Instruction::Drw { x: 1, y: 2, n: 0x10 }), None);
+        assert_eq!(encode(&Instruction::Jp(0x1000)), None);
+        assert_eq!(encode(&Instruction::Scd(0x10)), None);
+        assert_eq!(encode(&Instruction::Sys(0x0E0)), None); // cls
+        assert_eq!(encode(&Instruction::Sys(0x0C4)), None); // scd 4
+        assert_eq!(encode(&Instruction::Unknown(0x6112)), None);
+        assert_eq!(encode(&Instruction::Unknown(0x5121)), Some(0x5121));
     }
 
-    pub fn parse_nibble(&self, nibble: u8, opcode: u16) -> u8 {
-        if nibble == 0 {
-            (opcode & 0xF000) as u8
-        } else if nibble == 1 {
-            ((opcode & 0x0F00) >> 8) as u8
-        } else if nibble == 2 {
-            ((opcode & 0x00F0) >> 4) as u8
-        } else if nibble == 3{
-            (opcode & 0x000F) as u8
-        } else {
-            panic!("Nibble out of range.");
-        }
+    #[test]
+    fn operands_are_extracted() {
+        assert_eq!(decode(0xD125), Instruction::Drw { x: 1, y: 2, n: 5 });
+        assert_eq!(decode(0xA2F0), Instruction::LdI(0x2F0));
+        assert_eq!(decode(0x8AB6), Instruction::Shr { x: 0xA, y: 0xB });
+        assert_eq!(decode(0xF365), Instruction::LdXI(3));
+        assert_eq!(decode(0x5121), Instruction::Unknown(0x5121));
     }
 
-    // Get instruction details
-    pub fn get_debug_info(&self, instruction: Instruction, pc: u16, v1: u16, v2: u16, v3: u16) -> String {
-        match instruction {
-            Instruction::JP  => format!("0x{:x}: jp #{:x}", pc, v1),
-            Instruction::LdI => format!("0x{:x}: ld I, #{:x}", pc, v1),
-            Instruction::LdV => format!("0x{:x}: ld V{:x}, #{:x}", pc, v1, v2),
-            Instruction::DRW => format!("0x{:x}: drw V{:x}, V{:x}, #{:x}", pc, v1, v2, v3),
-            Instruction::AddI => format!("0x{:x}: add I, V{:x}", pc, v1),
-            Instruction::AddX => format!("0x{:x}: add V{:x}, #{:x}", pc, v1, v2),
-            Instruction::SeX => format!("0x{:x}: se V{:x}, #{:x}", pc, v1, v2),
-            Instruction::SeXY => format!("0x{:x}: se V{:x}, V{:x}", pc, v1, v2),
-            Instruction::LdXK => format!("0x{:x}: ld V{:x}, K", pc, v1),
-            Instruction::CLS => format!("0x{:x}: cls", pc),
-            Instruction::RET => format!("0x{:x}: ret", pc),
-            Instruction::CALL => format!("0x{:x}: call #{:x}", pc, v1),
-            Instruction::LdXY => format!("0x{:x}: ld V{:x}, V{:x}", pc, v1, v2),
-            Instruction::SHR => format!("0x{:x}: shr V{:x}", pc, v1),
-            Instruction::LdB => format!("0x{:x}: ld B, V{:x}", pc, v1),
-            Instruction::LdXI => format!("0x{:x}: ld V{:x}, [I]", pc, v1),
-            Instruction::LdF => format!("0x{:x}: ld F, V{:x}", pc, v1),
-            Instruction::RND => format!("0x{:x}: rnd V{:x}, #{:x}", pc, v1, v2),
-            Instruction::AddXY => format!("0x{:x}: add V{:x}, V{:x}", pc, v1, v2),
-            Instruction::SKP => format!("0x{:x}: skp V{:x}", pc, v1),
-            Instruction::SKNP => format!("0x{:x}: sknp V{:x}", pc, v1),
-            Instruction::LdDT => format!("0x{:x}: ld DT, V{:x}", pc, v1),
-            Instruction::LdST => format!("0x{:x}: ld ST, V{:x}", pc, v1),
-            Instruction::LdXDT => format!("0x{:x}: ld V{:x}, DT", pc, v1),
-            Instruction::SneX => format!("0x{:x}: sne V{:x}, B", pc, v1),
-            Instruction::SneXY => format!("0x{:x}: sne V{:x}, V{:x}", pc, v1, v2),
-            Instruction::LdIX => format!("0x{:x}: ld [I], V{:x}", pc, v1),
-            Instruction::OR => format!("0x{:x}: or V{:x}, V{:x}", pc, v1, v2),
-            Instruction::XOR => format!("0x{:x}: xor V{:x}, V{:x}", pc, v1, v2),
-            Instruction::AND => format!("0x{:x}: and V{:x}, V{:x}", pc, v1, v2),
-            Instruction::SUB => format!("0x{:x}: sub V{:x}, V{:x}", pc, v1, v2),
-            Instruction::SUBN => format!("0x{:x}: subn V{:x}, V{:x}", pc, v1, v2),
-            _ => format!("0x{:x}: Unknown", pc)
-        }
+    #[test]
+    fn display_matches_debug_format() {
+        assert_eq!(get_debug_info(&decode(0x6110), 0x200), "0x200: ld V1, #10");
+        assert_eq!(get_debug_info(&decode(0xD015), 0x202), "0x202: drw V0, V1, #5");
+        assert_eq!(decode(0x4304).to_string(), "sne V3, #4");
     }
-}
\ No newline at end of file
+}
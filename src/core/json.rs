@@ -0,0 +1,289 @@
+use std::fmt;
+
+// Just enough JSON for the debug adapter protocol. Objects keep their keys in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+pub static NULL: Json = Json::Null;
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { bytes: text.as_bytes(), at: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.at != parser.bytes.len() {
+            return Err(format!("trailing characters at {}", parser.at));
+        }
+        Ok(value)
+    }
+
+    // Null when missing, so lookups can be chained
+    pub fn get(&self, key: &str) -> &Json {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|field| field.0 == key).map_or(&NULL, |field| &field.1),
+            _ => &NULL
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref string) => Some(string),
+            _ => None
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Json::Number(number) if number.fract() == 0.0 => Some(number as i64),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(value) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match *self {
+            Json::Array(ref items) => items,
+            _ => &[]
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(value: &'a str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        Json::Array(items)
+    }
+}
+
+// object(vec![("name", "value".into())])
+pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(ref string) => write_string(f, string),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { "," } else { "" }, item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    at: usize
+}
+
+impl<'a> Parser<'a> {
+    fn whitespace(&mut self) {
+        while self.at < self.bytes.len() && (self.bytes[self.at] as char).is_ascii_whitespace() {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.at).cloned()
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        if self.bytes[self.at..].starts_with(word.as_bytes()) {
+            self.at += word.len();
+            Ok(())
+        } else {
+            Err(format!("expected {} at {}", word, self.at))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.at += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.at += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        Some(b']') => {
+                            self.at += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return Err(format!("expected , or ] at {}", self.at))
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.at += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        Some(b'}') => {
+                            self.at += 1;
+                            return Ok(Json::Object(fields));
+                        },
+                        _ => return Err(format!("expected , or }} at {}", self.at))
+                    }
+                }
+            },
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.at;
+                while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E') | Some(b'0'..=b'9') = self.peek() {
+                    self.at += 1;
+                }
+                let number = String::from_utf8_lossy(&self.bytes[start..self.at]);
+                number.parse().map(Json::Number).map_err(|_| format!("invalid number {}", number))
+            },
+            _ => Err(format!("unexpected character at {}", self.at))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.peek().ok_or("unterminated string")?;
+            self.at += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.peek().ok_or("unterminated string")?;
+                    self.at += 1;
+                    let c = match escaped {
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let hex = self.bytes.get(self.at..self.at + 4).ok_or("truncated \\u escape")?;
+                            self.at += 4;
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16)
+                                .map_err(|_| "invalid \\u escape".to_string())?;
+                            // surrogate pairs are not worth it here
+                            std::char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        other => other as char
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                _ => bytes.push(byte)
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "invalid utf-8 in string".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[3,-4.5],"name":"a \"b\"\n","ok":true,"x":null}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("seq").as_i64(), Some(1));
+        assert_eq!(json.get("arguments").get("lines").as_array().len(), 2);
+        assert_eq!(json.get("arguments").get("name").as_str(), Some("a \"b\"\n"));
+        assert_eq!(json.get("missing").get("deeper"), &Json::Null);
+        assert_eq!(json.to_string(), text);
+        assert_eq!(Json::parse(" [ ] ").unwrap(), Json::Array(Vec::new()));
+        assert_eq!(Json::parse(r#""é""#).unwrap().as_str(), Some("é"));
+        assert!(Json::parse("{\"a\":}").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+    }
+}
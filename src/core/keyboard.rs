@@ -20,4 +20,10 @@ impl Keyboard {
     pub fn pressed(&self, key: u8) -> bool {
         self.keyboard[key as usize]
     }
-}
\ No newline at end of file
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
+}
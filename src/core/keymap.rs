@@ -0,0 +1,198 @@
+// Which host keys press which CHIP-8 keys. Host keys are names like Q, Key1, NumPad7 or Up, the
+// frontend knows what they mean.
+//
+// The config file is a small subset of TOML:
+//
+//   preset = "qwertz"          # what the keys below change, qwerty unless given
+//
+//   [keys]
+//   5 = ["W", "Up"]            # replaces every host key of that CHIP-8 key
+//
+//   [rom.4f8e1c0a9b2d3e7f]     # only for the ROM with this hash, see rip8 -d
+//   preset = "numpad"
+//
+//   [rom.4f8e1c0a9b2d3e7f.keys]
+//   0 = "Space"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    pub keys: Vec<Vec<String>> // host keys for each of the 16 CHIP-8 keys
+}
+
+pub const PRESETS: [&str; 4] = ["qwerty", "qwertz", "azerty", "numpad"];
+
+// CHIP-8 keys in the order of the COSMAC VIP keypad, row by row:
+// 1 2 3 C
+// 4 5 6 D
+// 7 8 9 E
+// A 0 B F
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Array(Vec<String>)
+}
+
+impl Keymap {
+    // The left side of the keyboard laid out like the keypad, or the numeric keypad
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let layout = match name {
+            "qwerty" => ["Key1", "Key2", "Key3", "Key4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"],
+            "qwertz" => ["Key1", "Key2", "Key3", "Key4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V"],
+            "azerty" => ["Key1", "Key2", "Key3", "Key4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V"],
+            // digits press the same digit, A to F are the keys around them
+            "numpad" => ["NumPad1", "NumPad2", "NumPad3", "NumPadMinus", "NumPad4", "NumPad5", "NumPad6", "NumPadPlus",
+                         "NumPad7", "NumPad8", "NumPad9", "NumPadEnter", "NumPadSlash", "NumPad0", "NumPadAsterisk", "NumPadDot"],
+            _ => return None
+        };
+        let mut keymap = Keymap { keys: vec![Vec::new(); 16] };
+        for (host, &key) in layout.iter().zip(KEYPAD.iter()) {
+            keymap.keys[key as usize].push(host.to_string());
+        }
+        Some(keymap)
+    }
+
+    // The top level settings, then the ones for this ROM on top
+    pub fn parse(text: &str, rom_hash: u64) -> Result<Keymap, String> {
+        let entries = parse_toml(text)?;
+        let rom = format!("rom.{:016x}", rom_hash);
+        let rom_keys = format!("{}.keys", rom);
+        let mut keymap = Keymap::default();
+        for &(settings, keys) in [("", "keys"), (rom.as_str(), rom_keys.as_str())].iter() {
+            for &(_, line, _, ref value) in entries.iter().filter(|entry| entry.0 == settings && entry.2 == "preset") {
+                let name = match *value {
+                    Value::String(ref name) => name,
+                    _ => return Err(format!("line {}: preset needs a string", line))
+                };
+                keymap = Keymap::preset(name)
+                    .ok_or(format!("line {}: unknown preset {}, expected one of {}", line, name, PRESETS.join(", ")))?;
+            }
+            for &(_, line, ref key, ref value) in entries.iter().filter(|entry| entry.0 == keys) {
+                let chip8 = u8::from_str_radix(key.trim_start_matches("0x"), 16).ok().filter(|&key| key < 16)
+                    .ok_or(format!("line {}: {} is not a CHIP-8 key, expected 0 to F", line, key))?;
+                keymap.keys[chip8 as usize] = match *value {
+                    Value::String(ref host) => vec![host.clone()],
+                    Value::Array(ref hosts) => hosts.clone()
+                };
+            }
+        }
+        // anything else is most likely a typo
+        for &(ref path, line, ref key, _) in entries.iter() {
+            let known = match path.as_str() {
+                "" => key == "preset",
+                "keys" => true,
+                path => path.starts_with("rom.") && (path.matches('.').count() == 1 && key == "preset" ||
+                                                     path.matches('.').count() == 2 && path.ends_with(".keys"))
+            };
+            if !known {
+                return Err(format!("line {}: unknown setting {} in [{}]", line, key, path));
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::preset("qwerty").expect("qwerty")
+    }
+}
+
+// Every key = value line with the table it is in and its line number
+fn parse_toml(text: &str) -> Result<Vec<(String, usize, String, Value)>, String> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            table = name.split('.').map(|part| unquote(part.trim())).collect::<Vec<_>>().join(".");
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(format!("line {}: expected key = value", number))?;
+        let value = value.trim();
+        let value = if let Some(items) = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+            let items: Result<Vec<String>, String> = items.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| string(item).ok_or(format!("line {}: expected a quoted key name, got {}", number, item)))
+                .collect();
+            Value::Array(items?)
+        } else {
+            Value::String(string(value).ok_or(format!("line {}: expected a quoted string, got {}", number, value))?)
+        };
+        entries.push((table.clone(), number, unquote(key.trim()), value));
+    }
+    Ok(entries)
+}
+
+// Everything from a # that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn string(value: &str) -> Option<String> {
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).map(str::to_string)
+}
+
+// Keys may be bare or quoted
+fn unquote(key: &str) -> String {
+    string(key).unwrap_or_else(|| key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_every_key() {
+        for name in PRESETS.iter() {
+            let keymap = Keymap::preset(name).unwrap();
+            assert!(keymap.keys.iter().all(|hosts| hosts.len() == 1), "{}", name);
+        }
+        assert_eq!(Keymap::default().keys[0xA], vec!["Z"]);
+        assert_eq!(Keymap::preset("qwertz").unwrap().keys[0xA], vec!["Y"]);
+    }
+
+    #[test]
+    fn parses_overrides_per_rom() {
+        let config = r#"
+preset = "azerty" # french keyboards
+
+[keys]
+5 = ["Z", "Up"]
+0xF = "Space"
+
+[rom."00000000000000ff"]
+preset = "numpad"
+
+[rom.00000000000000ff.keys]
+"0" = "Key0"
+"#;
+        let keymap = Keymap::parse(config, 0x1).unwrap();
+        assert_eq!(keymap.keys[0x5], vec!["Z", "Up"]);
+        assert_eq!(keymap.keys[0xF], vec!["Space"]);
+        assert_eq!(keymap.keys[0x4], vec!["A"]);
+
+        let keymap = Keymap::parse(config, 0xFF).unwrap();
+        assert_eq!(keymap.keys[0x5], vec!["NumPad5"]);
+        assert_eq!(keymap.keys[0x0], vec!["Key0"]);
+
+        assert_eq!(Keymap::parse("[keys]\n10 = \"A\"", 0), Err("line 2: 10 is not a CHIP-8 key, expected 0 to F".to_string()));
+        assert_eq!(Keymap::parse("preset = \"dvorak\"", 0).unwrap_err(),
+                   "line 1: unknown preset dvorak, expected one of qwerty, qwertz, azerty, numpad");
+        assert!(Keymap::parse("[key]\n1 = \"A\"", 0).is_err());
+        assert!(Keymap::parse("[keys]\n1 = A", 0).is_err());
+    }
+}
@@ -5,4 +5,26 @@ pub mod keyboard;
 pub mod instruction;
 pub mod instructions;
 pub mod rom;
-pub mod screen;
\ No newline at end of file
+pub mod screen;
+pub mod error;
+pub mod quirks;
+pub mod rpl;
+pub mod random;
+pub mod clock;
+pub mod backend;
+pub mod dump;
+pub mod headless;
+pub mod state;
+pub mod rewind;
+pub mod movie;
+pub mod debugger;
+pub mod watch;
+pub mod disasm;
+pub mod asm;
+pub mod gdb;
+pub mod json;
+pub mod dap;
+pub mod trace;
+pub mod profile;
+pub mod coverage;
+pub mod keymap;
@@ -0,0 +1,141 @@
+use super::error::Rip8Error;
+use super::keyboard::Keyboard;
+use super::quirks::Quirks;
+use super::state::{self, StateReader, StateWriter};
+
+// Same header as save states, see state.rs
+pub const MAGIC: [u8; 4] = *b"R8MV";
+pub const VERSION: u16 = 2;
+
+// The keypad of every frame since power on, plus everything else a replay needs to come out the
+// same: the random seed at power on, the quirks and the instructions per frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub instructions_per_frame: usize,
+    pub frames: Vec<u16> // one bit per key
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], quirks: Quirks, seed: u64, instructions_per_frame: usize) -> Movie {
+        Movie {
+            rom_hash: state::rom_hash(rom),
+            quirks,
+            seed,
+            instructions_per_frame,
+            frames: Vec::new()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movie = StateWriter::with_header(MAGIC, VERSION, self.rom_hash);
+        movie.u8(self.quirks.bits());
+        movie.u64(self.seed);
+        movie.u32(self.instructions_per_frame as u32);
+        movie.u32(self.frames.len() as u32);
+        for keys in self.frames.iter() {
+            movie.u16(*keys);
+        }
+        movie.finish()
+    }
+
+    // Fails unless the movie was recorded with this ROM
+    pub fn from_bytes(bytes: &[u8], rom: &[u8]) -> Result<Movie, Rip8Error> {
+        Movie::read(bytes, state::rom_hash(rom)).map_err(|err| match err {
+            Rip8Error::InvalidState(reason) => Rip8Error::InvalidMovie(reason),
+            err => err
+        })
+    }
+
+    fn read(bytes: &[u8], rom_hash: u64) -> Result<Movie, Rip8Error> {
+        let mut movie = StateReader::with_header(bytes, MAGIC, VERSION, rom_hash)?;
+        let quirks = Quirks::from_bits(movie.u8()?);
+        let seed = movie.u64()?;
+        let instructions_per_frame = movie.u32()? as usize;
+        let mut frames = Vec::new();
+        for _ in 0..movie.u32()? {
+            frames.push(movie.u16()?);
+        }
+        movie.finish()?;
+        Ok(Movie { rom_hash, quirks, seed, instructions_per_frame, frames })
+    }
+}
+
+// A movie being recorded or played back by the Cpu
+pub enum Tape {
+    Recording(Movie),
+    Playing(Movie)
+}
+
+impl Tape {
+    // Called once per frame right after the keypad was polled. Recording stores the keypad, playback
+    // replaces it. Once a movie ran out the live input is left alone.
+    pub fn sample(&mut self, frame: u64, keyboard: &mut Keyboard) {
+        match *self {
+            Tape::Recording(ref mut movie) => {
+                let keys = keyboard.keyboard.iter().enumerate()
+                    .fold(0u16, |keys, (key, &pressed)| keys | (pressed as u16) << key);
+                movie.frames.truncate(frame as usize);
+                movie.frames.push(keys);
+            },
+            Tape::Playing(ref movie) => {
+                if let Some(keys) = movie.frames.get(frame as usize) {
+                    for key in 0..16 {
+                        keyboard.keyboard[key] = keys & (1 << key) != 0;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn finished(&self, frame: u64) -> bool {
+        match *self {
+            Tape::Recording(_) => false,
+            Tape::Playing(ref movie) => frame as usize >= movie.frames.len()
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        match *self {
+            Tape::Recording(ref movie) | Tape::Playing(ref movie) => movie
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let mut movie = Movie::new(&[0x12, 0x00], Quirks::schip(), 7, 15);
+        movie.frames = vec![0, 1, 0x8000, 0xFFFF];
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&bytes, &[0x12, 0x00]).unwrap(), movie);
+        assert!(matches!(Movie::from_bytes(&bytes, &[0x12, 0x02]), Err(Rip8Error::RomMismatch)));
+        assert!(matches!(Movie::from_bytes(&bytes[..20], &[0x12, 0x00]), Err(Rip8Error::InvalidMovie(_))));
+    }
+
+    #[test]
+    fn records_and_plays_keys() {
+        let mut tape = Tape::Recording(Movie::new(&[], Quirks::default(), 0, 10));
+        let mut keyboard = Keyboard::new();
+        keyboard.set(0x3);
+        tape.sample(0, &mut keyboard);
+        keyboard.unset(0x3);
+        keyboard.set(0xF);
+        tape.sample(1, &mut keyboard);
+        assert_eq!(tape.movie().frames, vec![0x0008, 0x8000]);
+
+        let mut tape = Tape::Playing(tape.movie().clone());
+        let mut keyboard = Keyboard::new();
+        tape.sample(0, &mut keyboard);
+        assert!(keyboard.pressed(0x3));
+        tape.sample(1, &mut keyboard);
+        assert!(!keyboard.pressed(0x3));
+        assert!(keyboard.pressed(0xF));
+        assert!(tape.finished(2));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use super::asm::Symbols;
+use super::instruction::Instruction;
+use super::instructions::decode;
+use super::json::{object, Json};
+
+const REPORT_ROWS: usize = 20; // per table of the text report
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub calls: u64,
+    pub inclusive: u64, // instructions from the call up to the return, callees included
+    pub exclusive: u64  // instructions run in the subroutine itself
+}
+
+// A subroutine that has not returned yet
+struct Frame {
+    target: u16,
+    start: u64 // instructions counted when it was called
+}
+
+// Counts executed instructions by address, by opcode and by subroutine. Subroutines are tracked
+// with a shadow of the call stack, every instruction counts as one cycle.
+#[derive(Default)]
+pub struct Profile {
+    pub instructions: u64,
+    pub addresses: HashMap<u16, u64>,
+    pub opcodes: HashMap<u16, u64>,
+    pub subroutines: HashMap<u16, Subroutine>,
+    frames: Vec<Frame> // only complete while it is as deep as the real stack
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    // Called before the instruction runs, sp being the stack depth at that point
+    pub fn record(&mut self, pc: u16, sp: u8, opcode: u16, instruction: &Instruction) {
+        // load_state, rewind and debuggers can move the stack around behind our back
+        self.frames.truncate(sp as usize);
+        let tracked = self.frames.len() == sp as usize;
+        self.instructions += 1;
+        *self.addresses.entry(pc).or_insert(0) += 1;
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+        if let Some(frame) = self.frames.last().filter(|_| tracked) {
+            self.subroutines.entry(frame.target).or_default().exclusive += 1;
+        }
+        match *instruction {
+            Instruction::Call(target) => {
+                self.subroutines.entry(target).or_default().calls += 1;
+                if tracked {
+                    self.frames.push(Frame { target, start: self.instructions });
+                }
+            },
+            Instruction::Ret if tracked => {
+                if let Some(frame) = self.frames.pop() {
+                    self.returned(frame);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // Executions per instruction kind, busiest first
+    pub fn kinds(&self) -> Vec<(String, u64)> {
+        let mut kinds = HashMap::new();
+        for (&opcode, &count) in self.opcodes.iter() {
+            *kinds.entry(kind(&decode(opcode))).or_insert(0) += count;
+        }
+        sorted(&kinds)
+    }
+
+    fn returned(&mut self, frame: Frame) {
+        // recursive calls are part of the outermost one already
+        if !self.frames.iter().any(|outer| outer.target == frame.target) {
+            self.subroutines.entry(frame.target).or_default().inclusive += self.instructions - frame.start;
+        }
+    }
+
+    // Counts subroutines that are still running as if they returned now
+    pub fn finish(&mut self) {
+        while let Some(frame) = self.frames.pop() {
+            self.returned(frame);
+        }
+    }
+
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mut report = format!("{} instructions\n\nHottest addresses\n{:>12} {:>7}  address\n", self.instructions, "count", "%");
+        for (address, count) in sorted(&self.addresses).into_iter().take(REPORT_ROWS) {
+            report.push_str(&format!("{:>12} {:>6.2}%  {}\n", count, percent(count), name(symbols, address)));
+        }
+
+        report.push_str(&format!("\nInstructions\n{:>12} {:>7}  kind\n", "count", "%"));
+        for (kind, count) in self.kinds().into_iter().take(REPORT_ROWS) {
+            report.push_str(&format!("{:>12} {:>6.2}%  {}\n", count, percent(count), kind));
+        }
+
+        report.push_str(&format!("\nSubroutines\n{:>12} {:>12} {:>7} {:>12} {:>7}  subroutine\n",
+                                 "calls", "inclusive", "%", "exclusive", "%"));
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (&address, subroutine) in subroutines.into_iter().take(REPORT_ROWS) {
+            report.push_str(&format!("{:>12} {:>12} {:>6.2}% {:>12} {:>6.2}%  {}\n",
+                                     subroutine.calls, subroutine.inclusive, percent(subroutine.inclusive),
+                                     subroutine.exclusive, percent(subroutine.exclusive), name(symbols, address)));
+        }
+        report
+    }
+
+    // Everything, not just the top of each table
+    pub fn to_json(&self, symbols: &Symbols) -> Json {
+        let addresses: Vec<Json> = sorted(&self.addresses).into_iter().map(|(address, count)| object(vec![
+            ("address", (address as i64).into()),
+            ("name", name(symbols, address).into()),
+            ("count", (count as i64).into())
+        ])).collect();
+        let kinds: Vec<Json> = self.kinds().into_iter().map(|(kind, count)| object(vec![
+            ("kind", kind.into()),
+            ("count", (count as i64).into())
+        ])).collect();
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        let subroutines: Vec<Json> = subroutines.into_iter().map(|(&address, subroutine)| object(vec![
+            ("address", (address as i64).into()),
+            ("name", name(symbols, address).into()),
+            ("calls", (subroutine.calls as i64).into()),
+            ("inclusive", (subroutine.inclusive as i64).into()),
+            ("exclusive", (subroutine.exclusive as i64).into())
+        ])).collect();
+        object(vec![
+            ("instructions", (self.instructions as i64).into()),
+            ("addresses", addresses.into()),
+            ("kinds", kinds.into()),
+            ("subroutines", subroutines.into())
+        ])
+    }
+}
+
+// The variant name, SneX for SneX { x: 3, byte: 4 }
+fn kind(instruction: &Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    debug.split([' ', '(']).next().unwrap_or("").to_string()
+}
+
+// Busiest first, ties by key so reports come out the same every time
+fn sorted<K: Clone + Ord>(counts: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.iter().map(|(key, &count)| (key.clone(), count)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+// loop+4 (0x20a), or just the address without symbols
+fn name(symbols: &Symbols, address: u16) -> String {
+    match symbols.labels.iter().filter(|label| label.0 <= address).max_by_key(|label| label.0) {
+        Some(&(at, ref label)) if at == address => format!("{} (0x{:03x})", label, address),
+        Some(&(at, ref label)) => format!("{}+{} (0x{:03x})", label, address - at, address),
+        None => format!("0x{:03x}", address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cpu::Cpu;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    #[test]
+    fn counts_subroutines_inclusive_and_exclusive() {
+        let rom = vec![
+            0x22, 0x06, // 200: call outer
+            0x12, 0x00, // 202: jp 200
+            0x00, 0x00,
+            0x60, 0x01, // 206 outer: ld V0, 1
+            0x22, 0x0E, // 208: call inner
+            0x00, 0xEE, // 20a: ret
+            0x00, 0x00,
+            0x00, 0xEE  // 20e inner: ret
+        ];
+        let mut cpu = Cpu::new(Rom::from_bytes(rom), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.set_profile(Profile::new());
+        for _ in 0..14 {
+            cpu.tick().unwrap();
+        }
+        let mut profile = cpu.take_profile().unwrap();
+        profile.finish();
+
+        // twice through both subroutines, then outer is left running after one instruction
+        assert_eq!(profile.instructions, 14);
+        assert_eq!(profile.addresses[&0x200], 3);
+        assert!(profile.kinds().contains(&("Ret".to_string(), 4)));
+        assert_eq!(profile.subroutines[&0x206], Subroutine { calls: 3, inclusive: 9, exclusive: 7 });
+        assert_eq!(profile.subroutines[&0x20E], Subroutine { calls: 2, inclusive: 2, exclusive: 2 });
+
+        let symbols = Symbols::parse("0x0206 outer\n0x020e inner\n");
+        let report = profile.report(&symbols);
+        assert!(report.contains("           3            9  64.29%            7  50.00%  outer (0x206)\n"), "{}", report);
+        assert!(report.contains("outer+4 (0x20a)"));
+        assert_eq!(profile.to_json(&symbols).get("subroutines").as_array()[1].get("name").as_str(), Some("inner (0x20e)"));
+    }
+}
@@ -0,0 +1,157 @@
+// How far Fx55/Fx65 move I
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IncrementI {
+    None,    // I stays where it was
+    X,       // I points at the last register, CHIP-48 got it one short
+    XPlusOne // I points behind the last register
+}
+
+// Behaviour that differs between the CHIP-8 interpreters ROMs were written for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_vy: bool,     // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place
+    pub increment_i: IncrementI,
+    pub jump_vx: bool,      // Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub vf_reset: bool,     // 8xy1/8xy2/8xy3 set VF to 0
+    pub clip: bool,         // DRW clips sprites at the screen edges instead of wrapping them
+    pub display_wait: bool, // DRW waits for the next vblank
+    pub xo_chip: bool       // 64 KiB of memory and the XO-CHIP opcodes
+}
+
+pub const PRESETS: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+pub const FLAGS: [&str; 7] = ["shift", "memory", "jump", "vf-reset", "clip", "display-wait", "xo-chip"];
+
+impl Quirks {
+    // The original COSMAC VIP interpreter
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            increment_i: IncrementI::XPlusOne,
+            jump_vx: false,
+            vf_reset: true,
+            clip: true,
+            display_wait: true,
+            xo_chip: false
+        }
+    }
+
+    // CHIP-48 on the HP48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            increment_i: IncrementI::X,
+            jump_vx: true,
+            vf_reset: false,
+            clip: true,
+            display_wait: false,
+            xo_chip: false
+        }
+    }
+
+    // SUPER-CHIP 1.1, which stopped touching I
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            increment_i: IncrementI::None,
+            jump_vx: true,
+            vf_reset: false,
+            clip: true,
+            display_wait: false,
+            xo_chip: false
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            increment_i: IncrementI::XPlusOne,
+            jump_vx: false,
+            vf_reset: false,
+            clip: false,
+            display_wait: false,
+            xo_chip: true
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None
+        }
+    }
+
+    pub fn set(&mut self, flag: &str, value: bool) -> Result<(), String> {
+        match flag {
+            "shift" => self.shift_vy = value,
+            "memory" => self.increment_i = if value { IncrementI::XPlusOne } else { IncrementI::None },
+            "jump" => self.jump_vx = value,
+            "vf-reset" => self.vf_reset = value,
+            "clip" => self.clip = value,
+            "display-wait" => self.display_wait = value,
+            "xo-chip" => self.xo_chip = value,
+            _ => return Err(format!("unknown quirk {}, expected one of {}", flag, FLAGS.join(", ")))
+        }
+        Ok(())
+    }
+
+    // The flags in the order of FLAGS for save states, memory takes two bits: 0 for none, 1 for
+    // x, 2 for x+1
+    pub fn bits(&self) -> u8 {
+        let increment_i = match self.increment_i {
+            IncrementI::None => 0,
+            IncrementI::X => 1,
+            IncrementI::XPlusOne => 2
+        };
+        self.shift_vy as u8 | increment_i << 1 | (self.jump_vx as u8) << 3 | (self.vf_reset as u8) << 4 |
+            (self.clip as u8) << 5 | (self.display_wait as u8) << 6 | (self.xo_chip as u8) << 7
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        let flag = |i: usize| bits & (1 << i) != 0;
+        Quirks {
+            shift_vy: flag(0),
+            increment_i: match bits >> 1 & 0x3 {
+                1 => IncrementI::X,
+                2 | 3 => IncrementI::XPlusOne,
+                _ => IncrementI::None
+            },
+            jump_vx: flag(3),
+            vf_reset: flag(4),
+            clip: flag(5),
+            display_wait: flag(6),
+            xo_chip: flag(7)
+        }
+    }
+
+    // Parses a command line override like "clip=off", memory also takes x or x+1 for how far I moves
+    pub fn apply_override(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, '=');
+        let flag = parts.next().unwrap_or("");
+        let value = match parts.next() {
+            Some("x") if flag == "memory" => {
+                self.increment_i = IncrementI::X;
+                return Ok(());
+            },
+            Some("x+1") if flag == "memory" => {
+                self.increment_i = IncrementI::XPlusOne;
+                return Ok(());
+            },
+            Some("on") | Some("1") | Some("true") => true,
+            Some("off") | Some("0") | Some("false") => false,
+            _ => return Err(format!("invalid quirk override {}, expected <quirk>=on|off or memory=x|x+1", arg))
+        };
+        self.set(flag, value)
+    }
+}
+
+// What rip8 did before there were quirks: shifts and loads leave I alone, Bnnn adds V0, sprites
+// wrap and nothing waits for vblank
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::from_bits(0)
+    }
+}
@@ -1,27 +1,117 @@
-const MEMORY_SIZE: usize = 4096;
+use std::cell::Cell;
+
+use super::coverage::{Coverage, FETCHED, READ, WRITTEN};
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const XO_MEMORY_SIZE: usize = 65536; // XO-CHIP
+
+// A range of memory the debugger wants to hear about, both ends inclusive
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool
+}
+
+// The first watched access since the last take_hit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub address: usize,
+    pub write: bool
+}
 
 pub struct Ram {
-    pub ram: [u8; MEMORY_SIZE],
+    pub ram: Vec<u8>,
+    watches: Vec<Watch>,
+    hit: Cell<Option<Hit>>,
+    coverage: Option<Coverage>
 }
 
 impl Ram {
     pub fn new() -> Ram {
+        Ram::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Ram {
         Ram {
-            ram: [0; MEMORY_SIZE],
+            ram: vec![0; size],
+            watches: Vec::new(),
+            hit: Cell::new(None),
+            coverage: None
         }
     }
 
-    pub fn write(&mut self, position: usize, byte: u8) {
-        self.ram[position] = byte;
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    // Returns None if position is outside of memory
+    pub fn write(&mut self, position: usize, byte: u8) -> Option<()> {
+        self.watched(position, true);
+        self.covered(position, WRITTEN);
+        self.ram.get_mut(position).map(|b| *b = byte)
+    }
+
+    // Reads an instruction word to run it. Fetching code never triggers a watch.
+    pub fn fetch(&self, position: usize) -> Option<u16> {
+        self.covered(position, FETCHED);
+        self.covered(position + 1, FETCHED);
+        self.read(position)
+    }
+
+    // Returns the 2 byte word at position without counting as an access, for looking ahead
+    pub fn read(&self, position: usize) -> Option<u16> {
+        let hi = *self.ram.get(position)? as u16;
+        let lo = *self.ram.get(position + 1)? as u16;
+        Some(hi << 8 | lo)
+    }
+
+    pub fn read_byte(&self, position: usize) -> Option<u8> {
+        self.watched(position, false);
+        self.covered(position, READ);
+        self.ram.get(position).cloned()
+    }
+
+    pub fn set_watches(&mut self, watches: Vec<Watch>) {
+        self.watches = watches;
+        self.hit.set(None);
+    }
+
+    pub fn take_hit(&self) -> Option<Hit> {
+        self.hit.take()
+    }
+
+    // Starts recording how every byte gets used, from scratch
+    pub fn track_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.ram.len()));
     }
 
-    // Returns the next instruction which is 2 bytes long
-    pub fn read(&mut self, position: usize) -> u16 {
-        let instruction: [u16; 2] = [self.ram[position] as u16, self.ram[position + 1] as u16];
-        instruction[0] << 8 | instruction[1]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
     }
 
-    pub fn read_byte(&mut self, position: usize) -> u8 {
-        self.ram[position]
+    fn covered(&self, address: usize, access: u8) {
+        if let Some(ref coverage) = self.coverage {
+            coverage.mark(address, access);
+        }
+    }
+
+    fn watched(&self, address: usize, write: bool) {
+        if self.hit.get().is_some() {
+            return;
+        }
+        let hit = self.watches.iter().any(|watch| {
+            address >= watch.start && address <= watch.end && if write { watch.write } else { watch.read }
+        });
+        if hit {
+            self.hit.set(Some(Hit { address, write }));
+        }
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
     }
 }
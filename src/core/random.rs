@@ -0,0 +1,77 @@
+extern crate rand;
+
+// Where CXKK gets its random bytes from. Embedders can hand the Cpu their own source, as long as
+// the sequence can be reproduced from the seed it reports.
+pub trait Random {
+    // Any value between 0 and 255
+    fn next_byte(&mut self) -> u8;
+    // Reseeding with this continues the exact same sequence from the current position
+    fn seed(&self) -> u64;
+    fn reseed(&mut self, seed: u64);
+}
+
+// SplitMix64, the whole state is the seed so it can be stored and restored as is
+pub struct SplitMix {
+    state: u64
+}
+
+impl SplitMix {
+    pub fn new(seed: u64) -> SplitMix {
+        SplitMix { state: seed }
+    }
+
+    // Seeded from the OS, used when no --seed was given
+    pub fn from_entropy() -> SplitMix {
+        SplitMix::new(rand::random())
+    }
+}
+
+impl Random for SplitMix {
+    fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    fn seed(&self) -> u64 {
+        self.state
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = SplitMix::new(42);
+        let mut b = SplitMix::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_byte(), b.next_byte());
+        }
+    }
+
+    #[test]
+    fn reseeding_with_seed_continues_sequence() {
+        let mut a = SplitMix::new(7);
+        a.next_byte();
+        let mut b = SplitMix::new(a.seed());
+        assert_eq!(a.next_byte(), b.next_byte());
+    }
+
+    #[test]
+    fn covers_every_byte() {
+        let mut random = SplitMix::new(0);
+        let mut seen = [false; 256];
+        for _ in 0..10000 {
+            seen[random.next_byte() as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
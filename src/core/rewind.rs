@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+
+pub const REWIND_FRAMES: usize = 600; // 10 seconds at 60 frames per second
+
+// The oldest snapshot is kept whole, every later one only as the packed XOR against the one before
+// it. Consecutive frames barely differ, so most deltas are a handful of bytes.
+enum Snapshot {
+    Full(Vec<u8>),
+    Delta(Vec<u8>)
+}
+
+// Bounded history of save states, newest last
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    newest: Option<Vec<u8>> // the newest snapshot unpacked, deltas are taken against it
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity + 1),
+            capacity: capacity.max(1),
+            newest: None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.newest = None;
+    }
+
+    // Forgets the oldest snapshot once the buffer is full
+    pub fn push(&mut self, state: Vec<u8>) {
+        let snapshot = match self.newest {
+            Some(ref newest) if newest.len() == state.len() => Snapshot::Delta(pack(&xor(newest, &state))),
+            _ => Snapshot::Full(state.clone())
+        };
+        self.snapshots.push_back(snapshot);
+        self.newest = Some(state);
+        if self.snapshots.len() > self.capacity {
+            self.drop_oldest();
+        }
+    }
+
+    // Takes the newest snapshot out of the buffer
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = match self.snapshots.pop_back() {
+            Some(Snapshot::Delta(delta)) => Some(xor(&newest, &unpack(&delta))),
+            // the state size changed here, the one before has to be rebuilt from the front
+            _ => self.rebuild_newest()
+        };
+        Some(newest)
+    }
+
+    fn rebuild_newest(&self) -> Option<Vec<u8>> {
+        self.snapshots.iter().fold(None, |state, snapshot| match *snapshot {
+            Snapshot::Full(ref full) => Some(full.clone()),
+            Snapshot::Delta(ref delta) => state.map(|state| xor(&state, &unpack(delta)))
+        })
+    }
+
+    fn drop_oldest(&mut self) {
+        let oldest = match self.snapshots.pop_front() {
+            Some(Snapshot::Full(state)) => state,
+            _ => return
+        };
+        // the next one was stored against the one we just dropped, it has to become whole
+        if let Some(next) = self.snapshots.front_mut() {
+            let state = match *next {
+                Snapshot::Delta(ref delta) => xor(&oldest, &unpack(delta)),
+                Snapshot::Full(_) => return
+            };
+            *next = Snapshot::Full(state);
+        }
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+// PackBits: a control byte below 128 is followed by that many plus one literal bytes, anything
+// above repeats the next byte 257 minus control times.
+fn pack(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let run = bytes[i..].iter().take(128).take_while(|&&byte| byte == bytes[i]).count();
+        if run > 1 {
+            out.push((257 - run) as u8);
+            out.push(bytes[i]);
+            i += run;
+            continue;
+        }
+        // literals until the next run of at least two, at most 128
+        let mut end = i + 1;
+        while end < bytes.len() && end - i < 128 && !(end + 1 < bytes.len() && bytes[end] == bytes[end + 1]) {
+            end += 1;
+        }
+        out.push((end - i - 1) as u8);
+        out.extend_from_slice(&bytes[i..end]);
+        i = end;
+    }
+    out
+}
+
+fn unpack(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let control = bytes[i] as usize;
+        if control < 128 {
+            out.extend_from_slice(&bytes[i + 1..i + control + 2]);
+            i += control + 2;
+        } else {
+            let len = out.len() + 257 - control;
+            out.resize(len, bytes[i + 1]);
+            i += 2;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(frame: u8) -> Vec<u8> {
+        let mut state = vec![0; 64];
+        state[0] = frame;
+        state[frame as usize % 64] ^= 0xFF;
+        state
+    }
+
+    #[test]
+    fn pops_newest_first() {
+        let mut rewind = Rewind::new(10);
+        for frame in 0..5 {
+            rewind.push(state(frame));
+        }
+        for frame in (0..5).rev() {
+            assert_eq!(rewind.pop(), Some(state(frame)));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn forgets_the_oldest() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..10 {
+            rewind.push(state(frame));
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(state(9)));
+        assert_eq!(rewind.pop(), Some(state(8)));
+        assert_eq!(rewind.pop(), Some(state(7)));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn packing_round_trips() {
+        let mut bytes = vec![0; 4096];
+        bytes[0x200..0x208].copy_from_slice(&[0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08]);
+        bytes[0xFFF] = 1;
+        let packed = pack(&bytes);
+        assert!(packed.len() < 100);
+        assert_eq!(unpack(&packed), bytes);
+        let noise: Vec<u8> = (0..1000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert_eq!(unpack(&pack(&noise)), noise);
+        assert!(unpack(&pack(&[])).is_empty());
+    }
+
+    #[test]
+    fn handles_states_changing_size() {
+        let mut rewind = Rewind::new(10);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 4]);
+        rewind.push(vec![1, 2]);
+        rewind.push(vec![1, 3]);
+        assert_eq!(rewind.pop(), Some(vec![1, 3]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 4]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3]));
+    }
+}
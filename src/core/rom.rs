@@ -1,19 +1,31 @@
 use std::fs::File;
 use std::io::Read;
 
+use super::error::Rip8Error;
+
 pub struct Rom {
     pub rom_path: String,
     pub rom: Vec<u8>
 }
 
 impl Rom {
-    pub fn new(rom_path: String) -> Rom {
+    pub fn new(rom_path: String) -> Result<Rom, Rip8Error> {
         let mut rom = Vec::<u8>::new();
-        let mut f = File::open(&rom_path).unwrap();
-        let _ = f.read_to_end(&mut rom);
-        Rom {
+        let read = File::open(&rom_path).and_then(|mut f| f.read_to_end(&mut rom));
+        if let Err(error) = read {
+            return Err(Rip8Error::RomIo { path: rom_path, error });
+        }
+        Ok(Rom {
             rom_path,
             rom
+        })
+    }
+
+    // For embedders that already have the ROM in memory
+    pub fn from_bytes(rom: Vec<u8>) -> Rom {
+        Rom {
+            rom_path: String::new(),
+            rom
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,31 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// SUPER-CHIP RPL user flags, kept in a file next to the ROM so they survive between runs.
+pub const RPL_FLAGS: usize = 16;
+
+pub fn path_for(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("rpl")
+}
+
+// A missing file just means the ROM never stored anything
+pub fn load(path: &Path) -> io::Result<[u8; RPL_FLAGS]> {
+    let mut flags = [0; RPL_FLAGS];
+    match File::open(path) {
+        Ok(mut f) => {
+            let mut bytes = Vec::new();
+            f.read_to_end(&mut bytes)?;
+            for (flag, byte) in flags.iter_mut().zip(bytes) {
+                *flag = byte;
+            }
+            Ok(flags)
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(flags),
+        Err(err) => Err(err)
+    }
+}
+
+pub fn save(path: &Path, flags: &[u8; RPL_FLAGS]) -> io::Result<()> {
+    File::create(path)?.write_all(flags)
+}
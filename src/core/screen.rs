@@ -1,11 +1,125 @@
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128; // SUPER-CHIP
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANES: u8 = 2; // XO-CHIP
+
+// Always backed by a hi-res buffer, in lo-res mode only the top left 64x32 pixels are used.
+// Every pixel holds one bit per XO-CHIP bitplane, so it is a colour index between 0 and 3.
 pub struct Screen {
-    pub screen: [[u8; 32]; 64]
+    pub screen: [[u8; HIRES_HEIGHT]; HIRES_WIDTH],
+    hires: bool,
+    planes: u8 // bitmask of the planes DRW, CLS and scrolling work on
 }
 
 impl Screen {
     pub fn new() -> Screen {
         Screen {
-            screen: [[0; 32]; 64]
+            screen: [[0; HIRES_HEIGHT]; HIRES_WIDTH],
+            hires: false,
+            planes: 1
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    // Switching resolution clears every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.screen = [[0; HIRES_HEIGHT]; HIRES_WIDTH];
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
+    }
+
+    // Clears the selected planes
+    pub fn clear(&mut self) {
+        for column in self.screen.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
+    }
+
+    // True if the pixel is set on any plane
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.screen[x][y] != 0
+    }
+
+    pub fn colour(&self, x: usize, y: usize) -> u8 {
+        self.screen[x][y]
+    }
+
+    // XORs a single pixel on the given plane, returns true if it was already set
+    pub fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let was_set = self.screen[x][y] & plane != 0;
+        self.screen[x][y] ^= plane;
+        was_set
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for x in 0..width {
+            for y in (0..height).rev() {
+                let source = if y >= n { self.screen[x][y - n] } else { 0 };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for x in 0..width {
+            for y in 0..height {
+                let source = if y + n < height { self.screen[x][y + n] } else { 0 };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for x in (0..width).rev() {
+            for y in 0..height {
+                let source = if x >= n { self.screen[x - n][y] } else { 0 };
+                self.move_pixel(x, y, source);
+            }
         }
     }
-}
\ No newline at end of file
+
+    pub fn scroll_left(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for x in 0..width {
+            for y in 0..height {
+                let source = if x + n < width { self.screen[x + n][y] } else { 0 };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    // Only the selected planes move when scrolling
+    fn move_pixel(&mut self, x: usize, y: usize, source: u8) {
+        self.screen[x][y] = (self.screen[x][y] & !self.planes) | (source & self.planes);
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        Screen::new()
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use super::error::Rip8Error;
+
+// Save states start with the magic and the format version, followed by the hash of the ROM they
+// were taken with. Everything after that is written by Cpu::save_state. Bump VERSION whenever that
+// layout changes, old states are rejected instead of being misread. States are not compressed so
+// they all have the same size for a given memory size, which keeps rewind deltas small.
+pub const MAGIC: [u8; 4] = *b"R8ST";
+pub const VERSION: u16 = 3;
+pub const SLOTS: u8 = 8;
+
+// Slots live next to the ROM, "game.ch8" keeps slot 1 in "game.1.state"
+pub fn path_for(rom_path: &str, slot: u8) -> PathBuf {
+    Path::new(rom_path).with_extension(format!("{}.state", slot))
+}
+
+// FNV-1a, just to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+pub struct StateWriter {
+    bytes: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new(rom_hash: u64) -> StateWriter {
+        StateWriter::with_header(MAGIC, VERSION, rom_hash)
+    }
+
+    // For other files laid out the same way, like movies
+    pub fn with_header(magic: [u8; 4], version: u16, rom_hash: u64) -> StateWriter {
+        let mut writer = StateWriter { bytes: magic.to_vec() };
+        writer.u16(version);
+        writer.u64(rom_hash);
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> StateReader<'a> {
+    // Checks the header, a state taken with another ROM is refused
+    pub fn new(bytes: &'a [u8], rom_hash: u64) -> Result<StateReader<'a>, Rip8Error> {
+        StateReader::with_header(bytes, MAGIC, VERSION, rom_hash)
+    }
+
+    pub fn with_header(bytes: &'a [u8], magic: [u8; 4], version: u16, rom_hash: u64) -> Result<StateReader<'a>, Rip8Error> {
+        let mut reader = StateReader { bytes };
+        if reader.take(magic.len())? != magic {
+            return Err(Rip8Error::InvalidState("wrong file type"));
+        }
+        if reader.u16()? != version {
+            return Err(Rip8Error::InvalidState("unsupported version"));
+        }
+        if reader.u64()? != rom_hash {
+            return Err(Rip8Error::RomMismatch);
+        }
+        Ok(reader)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Rip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Rip8Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Rip8Error> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Rip8Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Rip8Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), Rip8Error> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    // Trailing garbage means the state is not what we think it is
+    pub fn finish(self) -> Result<(), Rip8Error> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(Rip8Error::InvalidState("trailing data"))
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Rip8Error> {
+        if self.bytes.len() < n {
+            return Err(Rip8Error::InvalidState("truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_is_checked() {
+        let mut writer = StateWriter::new(42);
+        writer.u16(0x1234);
+        let state = writer.finish();
+        let mut reader = StateReader::new(&state, 42).unwrap();
+        assert_eq!(reader.u16().unwrap(), 0x1234);
+        assert!(reader.finish().is_ok());
+
+        assert!(matches!(StateReader::new(&state, 43), Err(Rip8Error::RomMismatch)));
+        assert!(matches!(StateReader::new(&state[..6], 42), Err(Rip8Error::InvalidState(_))));
+        let mut future = state.clone();
+        future[4] = 99;
+        assert!(matches!(StateReader::new(&future, 42), Err(Rip8Error::InvalidState(_))));
+    }
+
+    #[test]
+    fn slots_sit_next_to_the_rom() {
+        assert_eq!(path_for("roms/pong.ch8", 3), PathBuf::from("roms/pong.3.state"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::{self, Write};
+
+use super::instruction::Instruction;
+use super::registers::Registers;
+
+// Which instructions end up in the trace. Everything when left empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub ranges: Vec<(u16, u16)>, // inclusive pc ranges
+    pub mnemonics: Vec<String>   // first word of the disassembly, like drw or call
+}
+
+impl Filter {
+    // 200-2ff, or a single address
+    pub fn parse_range(arg: &str) -> Result<(u16, u16), String> {
+        let address = |text: &str| u16::from_str_radix(text.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid address {}", text));
+        let (start, end) = match arg.split_once('-') {
+            Some((start, end)) => (address(start)?, address(end)?),
+            None => (address(arg)?, address(arg)?)
+        };
+        if end < start {
+            return Err(format!("{} ends before it starts", arg));
+        }
+        Ok((start, end))
+    }
+
+    // drw,call,ret
+    pub fn parse_mnemonics(arg: &str) -> Vec<String> {
+        arg.split(',').map(|mnemonic| mnemonic.trim().to_lowercase()).filter(|mnemonic| !mnemonic.is_empty()).collect()
+    }
+
+    pub fn matches(&self, pc: u16, disassembly: &str) -> bool {
+        let mnemonic = disassembly.split_whitespace().next().unwrap_or("");
+        (self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| pc >= start && pc <= end)) &&
+            (self.mnemonics.is_empty() || self.mnemonics.iter().any(|wanted| wanted == mnemonic))
+    }
+}
+
+// Writes one line per executed instruction, see line for the format. The first write error stops
+// the trace and is kept for finish.
+pub struct Trace {
+    output: Box<dyn Write>,
+    filter: Filter,
+    error: Option<io::Error>
+}
+
+impl Trace {
+    pub fn new(output: Box<dyn Write>, filter: Filter) -> Trace {
+        Trace { output, filter, error: None }
+    }
+
+    // Called with the registers as they are before the instruction runs. long is the word after
+    // the opcode, only used by ld I, long.
+    pub fn record(&mut self, frame: u64, cycle: u64, registers: &Registers, opcode: u16, instruction: &Instruction, long: u16) {
+        if self.error.is_some() {
+            return;
+        }
+        let disassembly = disassemble(instruction, long);
+        if !self.filter.matches(registers.pc, &disassembly) {
+            return;
+        }
+        if let Err(err) = writeln!(self.output, "{}", line(frame, cycle, registers, opcode, &disassembly)) {
+            self.error = Some(err);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.output.flush()
+        }
+    }
+}
+
+fn disassemble(instruction: &Instruction, long: u16) -> String {
+    match *instruction {
+        Instruction::LdILong => format!("ld I, long #{:x}", long),
+        _ => instruction.to_string()
+    }
+}
+
+// The machine in the PC:0200 V0:00 style most emulators print, then frame, cycle and disassembly.
+// The first 22 columns line up with traces from elsewhere, cut them off there to diff:
+// PC:0200 OP:6110 V0:00 V1:00 .. VF:00 I:0000 SP:0 DT:00 ST:00 FR:000012 CY:0000000345 ld V1, #10
+pub fn line(frame: u64, cycle: u64, registers: &Registers, opcode: u16, disassembly: &str) -> String {
+    let mut line = format!("PC:{:04X} OP:{:04X}", registers.pc, opcode);
+    for (x, v) in registers.v.iter().enumerate() {
+        line.push_str(&format!(" V{:X}:{:02X}", x, v));
+    }
+    line.push_str(&format!(" I:{:04X} SP:{:X} DT:{:02X} ST:{:02X}",
+                           registers.i, registers.sp, registers.delay_timer, registers.sound_timer));
+    line.push_str(&format!(" FR:{:06} CY:{:010} {}", frame, cycle, disassembly));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::super::cpu::Cpu;
+    use super::super::quirks::Quirks;
+    use super::super::rom::Rom;
+
+    // Lets the test read what the Cpu wrote
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(rom: Vec<u8>, filter: Filter, instructions: usize) -> Vec<String> {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = Cpu::new(Rom::from_bytes(rom), Quirks::default(), false, false);
+        cpu.load_rom().unwrap();
+        cpu.set_trace(Trace::new(Box::new(Shared(written.clone())), filter));
+        for _ in 0..instructions {
+            cpu.tick().unwrap();
+        }
+        cpu.take_trace().unwrap().finish().unwrap();
+        let text = String::from_utf8(written.borrow().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn writes_the_state_before_each_instruction() {
+        // ld V3, #4, sne V3, #4, jp 202
+        let lines = trace(vec![0x63, 0x04, 0x43, 0x04, 0x12, 0x02], Filter::default(), 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "PC:0202 OP:4304 V0:00 V1:00 V2:00 V3:04 V4:00 V5:00 V6:00 V7:00 \
+                              V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 SP:0 DT:00 ST:00 \
+                              FR:000000 CY:0000000002 sne V3, #4");
+        // the columns other emulators print come first, in the same order
+        let columns: Vec<&str> = lines[1].split(' ').take(22).collect();
+        assert_eq!(columns[..2], ["PC:0202", "OP:4304"]);
+        assert_eq!(columns[17..], ["VF:00", "I:0000", "SP:0", "DT:00", "ST:00"]);
+    }
+
+    #[test]
+    fn filters_by_pc_and_mnemonic() {
+        let rom = vec![0x63, 0x04, 0x43, 0x04, 0x12, 0x02];
+        let filter = Filter { ranges: vec![Filter::parse_range("202-203").unwrap()], mnemonics: Vec::new() };
+        let lines = trace(rom.clone(), filter, 5);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.contains("PC:0202")));
+        let filter = Filter { ranges: Vec::new(), mnemonics: Filter::parse_mnemonics("JP, ld") };
+        let lines = trace(rom, filter, 5);
+        assert_eq!(lines.len(), 3);
+        assert!(Filter::parse_range("300-200").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use super::cpu::Cpu;
+use super::instruction::Instruction;
+use super::ram::{Hit, Watch};
+use super::registers::Registers;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St
+}
+
+impl Register {
+    pub fn parse(word: &str) -> Result<Register, String> {
+        let register = match word.to_lowercase().as_str() {
+            "i" => Register::I,
+            "pc" => Register::Pc,
+            "sp" => Register::Sp,
+            "dt" => Register::Dt,
+            "st" => Register::St,
+            name if name.len() == 2 && name.starts_with('v') => {
+                Register::V(u8::from_str_radix(&name[1..], 16).map_err(|_| format!("invalid register {}", word))?)
+            },
+            _ => return Err(format!("invalid register {}", word))
+        };
+        Ok(register)
+    }
+
+    pub fn read(&self, registers: &Registers) -> u16 {
+        match *self {
+            Register::V(x) => registers.v[x as usize & 0xF] as u16,
+            Register::I => registers.i,
+            Register::Pc => registers.pc,
+            Register::Sp => registers.sp as u16,
+            Register::Dt => registers.delay_timer as u16,
+            Register::St => registers.sound_timer as u16
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => write!(f, "i"),
+            Register::Pc => write!(f, "pc"),
+            Register::Sp => write!(f, "sp"),
+            Register::Dt => write!(f, "dt"),
+            Register::St => write!(f, "st")
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Memory(usize), // [addr], one byte
+    Value(u16)
+}
+
+impl Operand {
+    // Registers by name, memory as a hex address in brackets, anything else is a value
+    fn parse(word: &str) -> Result<Operand, String> {
+        if word.starts_with('[') && word.ends_with(']') {
+            let address = &word[1..word.len() - 1];
+            return usize::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map(Operand::Memory)
+                .map_err(|_| format!("invalid address {}", address));
+        }
+        if let Ok(register) = Register::parse(word) {
+            return Ok(Operand::Register(register));
+        }
+        let value = match word.strip_prefix("0x") {
+            Some(digits) => u16::from_str_radix(digits, 16),
+            None => word.parse()
+        };
+        value.map(Operand::Value).map_err(|_| format!("invalid operand {}", word))
+    }
+
+    fn value(&self, cpu: &Cpu) -> u16 {
+        match *self {
+            Operand::Register(register) => register.read(cpu.registers()),
+            // peeked so conditions never trigger memory watchpoints
+            Operand::Memory(address) => cpu.ram().ram.get(address).cloned().unwrap_or(0) as u16,
+            Operand::Value(value) => value
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(register) => write!(f, "{}", register),
+            Operand::Memory(address) => write!(f, "[0x{:x}]", address),
+            Operand::Value(value) => write!(f, "0x{:x}", value)
+        }
+    }
+}
+
+// Longest first, so <= is not read as <
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+// Something like "v5 == 3" or "[0x300] != i"
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub left: Operand,
+    pub comparison: &'static str,
+    pub right: Operand
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        for comparison in COMPARISONS.iter() {
+            if let Some(at) = text.find(comparison) {
+                let left = Operand::parse(text[..at].trim())?;
+                let right = Operand::parse(text[at + comparison.len()..].trim())?;
+                return Ok(Condition { left, comparison, right });
+            }
+        }
+        Err(format!("invalid condition {}, expected <a> <==|!=|<|<=|>|>=> <b>", text))
+    }
+
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        let (left, right) = (self.left.value(cpu), self.right.value(cpu));
+        match self.comparison {
+            "==" => left == right,
+            "!=" => left != right,
+            "<" => left < right,
+            "<=" => left <= right,
+            ">" => left > right,
+            _ => left >= right
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.left, self.comparison, self.right)
+    }
+}
+
+// Stops after an instruction did something interesting
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory(Watch),
+    Register(Register),
+    AnyV,           // any of V0 to VF changed
+    Collision,      // DRW set VF
+    KeyWait,        // LdXK started waiting for a key
+    StackDepth(u8)  // more than this many calls deep
+}
+
+// The machine right before the last instruction ran
+pub struct Before {
+    pub registers: Registers,
+    pub instruction: Instruction
+}
+
+impl Watchpoint {
+    // Describes what happened if the last instruction triggered this watchpoint
+    pub fn check(&self, before: &Before, cpu: &Cpu, hit: Option<Hit>, next: Option<Instruction>) -> Option<String> {
+        let now = cpu.registers();
+        match *self {
+            Watchpoint::Memory(watch) => hit
+                .filter(|hit| hit.address >= watch.start && hit.address <= watch.end)
+                .map(|hit| format!("{} 0x{:x}", if hit.write { "write to" } else { "read from" }, hit.address)),
+            Watchpoint::Register(register) => {
+                let (old, new) = (register.read(&before.registers), register.read(now));
+                if old != new { Some(format!("{} changed from 0x{:x} to 0x{:x}", register, old, new)) } else { None }
+            },
+            Watchpoint::AnyV => (0..16).find(|&x| before.registers.v[x] != now.v[x])
+                .map(|x| format!("v{:x} changed from 0x{:x} to 0x{:x}", x, before.registers.v[x], now.v[x])),
+            Watchpoint::Collision => match before.instruction {
+                Instruction::Drw { .. } if now.v[0xF] != 0 => Some("sprite collision".to_string()),
+                _ => None
+            },
+            Watchpoint::KeyWait => match next {
+                Some(Instruction::LdXK(_)) if before.registers.pc != now.pc => Some("waiting for a key".to_string()),
+                _ => None
+            },
+            Watchpoint::StackDepth(depth) if now.sp > depth && before.registers.sp <= depth =>
+                Some(format!("stack depth {}", now.sp)),
+            Watchpoint::StackDepth(_) => None
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory(watch) => {
+                let access = match (watch.read, watch.write) {
+                    (true, true) => "access",
+                    (true, false) => "read",
+                    _ => "write"
+                };
+                if watch.start == watch.end {
+                    write!(f, "{} 0x{:x}", access, watch.start)
+                } else {
+                    write!(f, "{} 0x{:x}-0x{:x}", access, watch.start, watch.end)
+                }
+            },
+            Watchpoint::Register(register) => write!(f, "change of {}", register),
+            Watchpoint::AnyV => write!(f, "change of any v register"),
+            Watchpoint::Collision => write!(f, "sprite collision"),
+            Watchpoint::KeyWait => write!(f, "key wait"),
+            Watchpoint::StackDepth(depth) => write!(f, "stack deeper than {}", depth)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conditions() {
+        let condition = Condition::parse("v5 == 3").unwrap();
+        assert_eq!(condition, Condition {
+            left: Operand::Register(Register::V(5)),
+            comparison: "==",
+            right: Operand::Value(3)
+        });
+        let condition = Condition::parse("[300]<=0x10").unwrap();
+        assert_eq!(condition.left, Operand::Memory(0x300));
+        assert_eq!(condition.comparison, "<=");
+        assert_eq!(condition.to_string(), "[0x300] <= 0x10");
+        assert!(Condition::parse("v5 = 3").is_err());
+        assert!(Condition::parse("vz == 3").is_err());
+    }
+}
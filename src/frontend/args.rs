@@ -0,0 +1,174 @@
+use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use rip8::Quirks;
+use rip8::core::keymap::PRESETS as KEYMAPS;
+use rip8::core::quirks::PRESETS;
+use rip8::core::cpu::INSTRUCTIONS_PER_FRAME;
+use rip8::core::registers::TIMER_RATE;
+use rip8::core::rewind::REWIND_FRAMES;
+use rip8::core::trace::Filter;
+
+pub const USAGE: &str = "usage: rip8 <rom> [-d] [-i] [--quirks <preset>] [--quirk <name>=on|off]... \
+                         [--ipf <n> | --hz <n>] [--speed <multiplier>] [--benchmark] [--seed <n>] [--rewind <frames>] \
+                         [--record <movie> | --play <movie>] [--gdb <host:port>] \
+                         [--trace <file> [--trace-pc <start>-<end>]... [--trace-only <mnemonic>,...]] \
+                         [--profile | --profile-json <file>] [--symbols <file>] [--coverage <map>] [--coverage-png <image>] \
+                         [--keymap <preset>|<file>]\n       \
+                         rip8 --dap [<host:port>] [<rom>]\n       \
+                         rip8 disasm <rom> [-o <file>]\n       \
+                         rip8 asm <source> [-o <rom>] [--symbols <file>]";
+
+pub struct Args {
+    pub rom_path: String,
+    pub debug: bool,
+    pub interactive: bool,
+    pub quirks: Quirks,
+    pub ipf: usize,
+    pub speed: f64,
+    pub benchmark: bool,
+    pub seed: Option<u64>,
+    pub rewind_frames: usize,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub gdb: Option<String>,
+    pub dap: bool,
+    pub dap_address: Option<String>, // stdio when missing
+    pub trace: Option<String>,
+    pub trace_filter: Filter,
+    pub profile: bool,
+    pub profile_json: Option<String>,
+    pub symbols: Option<String>, // the ROM with .sym when missing
+    pub coverage: Option<String>,
+    pub coverage_png: Option<String>,
+    pub keymap: Option<String> // a preset or a config file
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        Args::parse_from(env::args().skip(1))
+    }
+
+    pub fn parse_from<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+        let mut args = args.peekable();
+        let mut parsed = Args {
+            rom_path: String::new(),
+            debug: false,
+            interactive: false,
+            quirks: Quirks::default(),
+            ipf: INSTRUCTIONS_PER_FRAME,
+            speed: 1.0,
+            benchmark: false,
+            seed: None,
+            rewind_frames: REWIND_FRAMES,
+            record: None,
+            play: None,
+            gdb: None,
+            dap: false,
+            dap_address: None,
+            trace: None,
+            trace_filter: Filter::default(),
+            profile: false,
+            profile_json: None,
+            symbols: None,
+            coverage: None,
+            coverage_png: None,
+            keymap: None
+        };
+        // per flag overrides win over the preset, no matter the order they were given in
+        let mut overrides = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-d" => parsed.debug = true,
+                "-i" => parsed.interactive = true,
+                "--quirks" => {
+                    let name = args.next().ok_or("--quirks needs a preset")?;
+                    parsed.quirks = Quirks::preset(&name)
+                        .ok_or(format!("unknown preset {}, expected one of {}", name, PRESETS.join(", ")))?;
+                },
+                "--quirk" => overrides.push(args.next().ok_or("--quirk needs <name>=on|off")?),
+                "--ipf" => parsed.ipf = number(&arg, args.next())?,
+                "--hz" => {
+                    let hz: usize = number(&arg, args.next())?;
+                    // rounded to the nearest whole number of instructions per 60Hz frame
+                    parsed.ipf = (hz + TIMER_RATE as usize / 2) / TIMER_RATE as usize;
+                },
+                "--speed" => {
+                    parsed.speed = number(&arg, args.next())?;
+                    if parsed.speed <= 0.0 {
+                        return Err("--speed needs a multiplier above 0".to_string());
+                    }
+                },
+                "--benchmark" => parsed.benchmark = true,
+                "--seed" => parsed.seed = Some(number(&arg, args.next())?),
+                "--rewind" => parsed.rewind_frames = number(&arg, args.next())?,
+                "--record" => parsed.record = Some(args.next().ok_or("--record needs a file")?),
+                "--play" => parsed.play = Some(args.next().ok_or("--play needs a file")?),
+                "--gdb" => parsed.gdb = Some(args.next().ok_or("--gdb needs an address like 127.0.0.1:1234")?),
+                "--trace" => parsed.trace = Some(args.next().ok_or("--trace needs a file")?),
+                "--trace-pc" => parsed.trace_filter.ranges.push(Filter::parse_range(&args.next().ok_or("--trace-pc needs <start>-<end>")?)?),
+                "--trace-only" => parsed.trace_filter.mnemonics.extend(Filter::parse_mnemonics(&args.next().ok_or("--trace-only needs mnemonics like drw,call")?)),
+                "--profile" => parsed.profile = true,
+                "--profile-json" => parsed.profile_json = Some(args.next().ok_or("--profile-json needs a file")?),
+                "--symbols" => parsed.symbols = Some(args.next().ok_or("--symbols needs a file")?),
+                "--coverage" => parsed.coverage = Some(args.next().ok_or("--coverage needs a file")?),
+                "--coverage-png" => parsed.coverage_png = Some(args.next().ok_or("--coverage-png needs a file")?),
+                "--keymap" => parsed.keymap = Some(args.next().ok_or(format!("--keymap needs a file or one of {}", KEYMAPS.join(", ")))?),
+                "--dap" => {
+                    parsed.dap = true;
+                    // the address is optional, anything else is the ROM or the next option
+                    if args.peek().is_some_and(|next| next.parse::<SocketAddr>().is_ok()) {
+                        parsed.dap_address = args.next();
+                    }
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => parsed.rom_path = arg
+            }
+        }
+        for arg in overrides {
+            parsed.quirks.apply_override(&arg)?;
+        }
+        if parsed.record.is_some() && parsed.play.is_some() {
+            return Err("--record and --play can not be used together".to_string());
+        }
+        if parsed.interactive && parsed.gdb.is_some() {
+            return Err("-i and --gdb can not be used together".to_string());
+        }
+        if parsed.dap && (parsed.interactive || parsed.gdb.is_some()) {
+            return Err("--dap can not be used together with -i or --gdb".to_string());
+        }
+        // the -d instruction echo goes to stdout, which the protocol needs for itself
+        if parsed.dap && parsed.dap_address.is_none() && parsed.debug {
+            return Err("-d needs --dap to listen on an address, stdout is taken by the protocol".to_string());
+        }
+        // with --dap the editor says which ROM to launch
+        if parsed.rom_path.is_empty() && !parsed.dap {
+            return Err(USAGE.to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+fn number<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a number", option))?;
+    value.parse().map_err(|_| format!("invalid number {} for {}", value, option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn dap_over_stdio_keeps_stdout_to_itself() {
+        let args = parse(&["--dap"]).unwrap();
+        assert!(args.dap && args.dap_address.is_none() && args.rom_path.is_empty());
+        assert_eq!(parse(&["--dap", "127.0.0.1:4711", "game.ch8"]).unwrap().dap_address.as_deref(), Some("127.0.0.1:4711"));
+        assert!(parse(&["--dap", "-d"]).is_err());
+        assert!(parse(&["--dap", "127.0.0.1:4711", "-d"]).is_ok());
+        assert!(parse(&["--dap", "-i"]).is_err());
+    }
+}
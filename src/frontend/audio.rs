@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use sdl2;
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use rip8::core::backend::{AudioSink, Tone};
+
+const PATTERN_BITS: f32 = 128.0;
+
+struct Beep {
+    tone: Arc<Mutex<Tone>>,
+    freq: f32,
+    phase: f32,
+    volume: f32
+}
+
+impl AudioCallback for Beep {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let tone = self.tone.lock().unwrap();
+        match tone.pattern {
+            Some(pattern) => {
+                // Play the 128 bit pattern, one bit after the other
+                for x in out.iter_mut() {
+                    let bit = self.phase as usize;
+                    let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    *x = if set { self.volume } else { -self.volume };
+                    self.phase = (self.phase + tone.rate / self.freq) % PATTERN_BITS;
+                }
+            },
+            None => {
+                // Generate a square wave
+                for x in out.iter_mut() {
+                    *x = if self.phase < 0.5 { self.volume } else { -self.volume };
+                    self.phase = (self.phase + 440.0 / self.freq) % 1.0;
+                }
+            }
+        }
+    }
+}
+
+// SDL2 audio on its own thread, beeps for as long as the tone is playing
+pub struct Beeper {
+    tone: Arc<Mutex<Tone>>
+}
+
+impl Beeper {
+    pub fn start() -> Beeper {
+        Beeper { tone: start_beeper() }
+    }
+}
+
+impl AudioSink for Beeper {
+    fn play(&mut self, tone: &Tone) {
+        *self.tone.lock().unwrap() = *tone;
+    }
+}
+
+fn start_beeper() -> Arc<Mutex<Tone>> {
+    let tone = Arc::new(Mutex::new(Tone::default()));
+    let device_tone = tone.clone();
+    let callback_tone = tone.clone();
+    thread::spawn(move || {
+        let sdl_context = sdl2::init().unwrap();
+        let audio_subsystem = sdl_context.audio().unwrap();
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),  // mono
+            samples: None       // default sample size
+        };
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            // initialize the audio callback
+            Beep {
+                tone: callback_tone,
+                freq: spec.freq as f32,
+                phase: 0.0,
+                volume: 0.25
+            }
+        }).unwrap();
+        loop {
+            if device_tone.lock().unwrap().playing {
+                device.resume();
+            } else {
+                device.pause();
+            }
+            thread::sleep(Duration::from_millis(1000 / 60));
+        }
+    });
+    tone
+}
@@ -0,0 +1,4 @@
+pub mod audio;
+pub mod args;
+pub mod window;
+pub mod tools;
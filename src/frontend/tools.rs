@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+use rip8::core::asm::Assembler;
+use rip8::core::disasm;
+use rip8::core::registers::START_ADDRESS;
+
+pub const DISASM_USAGE: &str = "usage: rip8 disasm <rom> [-o <file>]";
+pub const ASM_USAGE: &str = "usage: rip8 asm <source> [-o <rom>] [--symbols <file>]";
+
+// rip8 disasm, prints a listing the assembler reads back in
+pub fn disasm(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    let mut rom_path = None;
+    let mut out_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = Some(args.next().ok_or("-o needs a file")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => rom_path = Some(arg)
+        }
+    }
+    let rom_path = rom_path.ok_or(DISASM_USAGE)?;
+    let rom = fs::read(rom_path).map_err(|err| format!("Unable to read {}: {}", rom_path, err))?;
+    let listing = disasm::disassemble(&rom, START_ADDRESS)?;
+    match out_path {
+        Some(path) => fs::write(path, listing).map_err(|err| format!("Unable to write {}: {}", path, err)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        }
+    }
+}
+
+// rip8 asm, writes the ROM next to the source unless told otherwise
+pub fn asm(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    let mut source_path = None;
+    let mut out_path = None;
+    let mut symbols_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = Some(args.next().ok_or("-o needs a file")?),
+            "--symbols" => symbols_path = Some(args.next().ok_or("--symbols needs a file")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => source_path = Some(arg)
+        }
+    }
+    let source_path = Path::new(source_path.ok_or(ASM_USAGE)?);
+    let assembly = Assembler::new(START_ADDRESS).assemble_file(source_path).map_err(|errors| {
+        errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n")
+    })?;
+    let out_path = match out_path {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => source_path.with_extension("ch8")
+    };
+    fs::write(&out_path, &assembly.rom).map_err(|err| format!("Unable to write {}: {}", out_path.display(), err))?;
+    if let Some(path) = symbols_path {
+        fs::write(path, assembly.symbol_file()).map_err(|err| format!("Unable to write {}: {}", path, err))?;
+    }
+    Ok(())
+}
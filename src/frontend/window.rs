@@ -0,0 +1,125 @@
+use minifb::{Key, KeyRepeat, WindowOptions, Window, Scale};
+use rip8::core::backend::{AudioSink, DisplaySink, InputSource, Tone};
+use rip8::core::keyboard::Keyboard;
+use rip8::core::keymap::Keymap;
+use rip8::core::screen::{Screen, HIRES_WIDTH, HIRES_HEIGHT};
+use frontend::audio::Beeper;
+
+// Background, plane 1, plane 2 and both planes
+const PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
+
+// Every key a keymap can name, by its minifb name
+const HOST_KEYS: [Key; 106] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8,
+    Key::Key9, Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L,
+    Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y,
+    Key::Z, Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10,
+    Key::F11, Key::F12, Key::F13, Key::F14, Key::F15, Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal, Key::LeftBracket, Key::Minus,
+    Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash, Key::Backspace, Key::Delete, Key::End,
+    Key::Enter, Key::Escape, Key::Home, Key::Insert, Key::Menu, Key::PageDown, Key::PageUp, Key::Pause,
+    Key::Space, Key::Tab, Key::NumLock, Key::CapsLock, Key::ScrollLock, Key::LeftShift, Key::RightShift,
+    Key::LeftCtrl, Key::RightCtrl, Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9, Key::NumPadDot, Key::NumPadSlash,
+    Key::NumPadAsterisk, Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter, Key::LeftAlt, Key::RightAlt,
+    Key::LeftSuper, Key::RightSuper
+];
+
+// F1 to F8 load save state slots 1 to 8, with shift held they save
+const SLOT_KEYS: [Key; 8] = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8];
+
+pub enum Hotkey {
+    SaveState(u8),
+    LoadState(u8)
+}
+
+// The minifb window plus SDL2 audio
+pub struct Desktop {
+    window: Window,
+    buffer: Vec<u32>, // sized for SUPER-CHIP hi-res, lo-res frames get scaled up by 2
+    beeper: Beeper,
+    keymap: Vec<(Key, u8)>
+}
+
+impl Desktop {
+    pub fn new() -> Result<Desktop, String> {
+        let window = Window::new("rip8", HIRES_WIDTH, HIRES_HEIGHT,
+                                 WindowOptions {
+                                     resize: false,
+                                     scale: Scale::X4,
+                                     ..WindowOptions::default()
+                                 }).map_err(|err| format!("Unable to create window {}", err))?;
+        Ok(Desktop {
+            window,
+            buffer: vec![0; HIRES_WIDTH * HIRES_HEIGHT],
+            beeper: Beeper::start(),
+            keymap: host_keymap(&Keymap::default()).expect("default keymap")
+        })
+    }
+
+    // Fails on key names minifb does not know
+    pub fn set_keymap(&mut self, keymap: &Keymap) -> Result<(), String> {
+        self.keymap = host_keymap(keymap)?;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.window.is_key_down(key)
+    }
+
+    // The hotkey pressed since the last frame, if any
+    pub fn hotkey(&self) -> Option<Hotkey> {
+        let shift = self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        SLOT_KEYS.iter().position(|&key| self.window.is_key_pressed(key, KeyRepeat::No)).map(|i| {
+            let slot = i as u8 + 1;
+            if shift { Hotkey::SaveState(slot) } else { Hotkey::LoadState(slot) }
+        })
+    }
+}
+
+impl InputSource for Desktop {
+    fn poll(&mut self, keyboard: &mut Keyboard) {
+        for key in 0..16 {
+            keyboard.unset(key);
+        }
+        for &(host, key) in self.keymap.iter() {
+            if self.window.is_key_down(host) {
+                keyboard.set(key);
+            }
+        }
+    }
+}
+
+impl DisplaySink for Desktop {
+    fn present(&mut self, screen: &Screen) {
+        let scale = HIRES_WIDTH / screen.width();
+        for y in 0..HIRES_HEIGHT {
+            for x in 0..HIRES_WIDTH {
+                self.buffer[y * HIRES_WIDTH + x] = PALETTE[screen.colour(x / scale, y / scale) as usize];
+            }
+        }
+        self.window.update_with_buffer(&self.buffer).unwrap();
+    }
+}
+
+impl AudioSink for Desktop {
+    fn play(&mut self, tone: &Tone) {
+        self.beeper.play(tone);
+    }
+}
+
+fn host_keymap(keymap: &Keymap) -> Result<Vec<(Key, u8)>, String> {
+    let mut keys = Vec::new();
+    for (chip8, hosts) in keymap.keys.iter().enumerate() {
+        for name in hosts.iter() {
+            let host = HOST_KEYS.iter().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+                .ok_or(format!("unknown key {} for {:X}", name, chip8))?;
+            keys.push((*host, chip8 as u8));
+        }
+    }
+    Ok(keys)
+}
@@ -0,0 +1,12 @@
+pub mod core;
+
+pub use core::clock::Clock;
+pub use core::cpu::Cpu;
+pub use core::error::Rip8Error;
+pub use core::keyboard::Keyboard;
+pub use core::quirks::Quirks;
+pub use core::ram::Ram;
+pub use core::registers::Registers;
+pub use core::random::{Random, SplitMix};
+pub use core::rom::Rom;
+pub use core::screen::Screen;
@@ -1,156 +1,300 @@
-#[macro_use]
-extern crate lazy_static;
 extern crate minifb;
-extern crate time;
+extern crate rip8;
+extern crate sdl2;
 
-mod core;
+mod frontend;
 
 use std::env;
-use std::thread;
-use core::cpu::Cpu;
-use minifb::{Key, WindowOptions, Window, Scale};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use rip8::{Clock, Cpu};
+use rip8::core::rom::Rom;
+use rip8::core::backend::{AudioSink, DisplaySink, Tone};
+use rip8::core::asm::Symbols;
+use rip8::core::dap::DapServer;
+use rip8::core::gdb::GdbStub;
+use rip8::core::keymap::Keymap;
+use rip8::core::movie::Movie;
+use rip8::core::rpl;
+use rip8::core::profile::Profile;
+use rip8::core::state;
+use rip8::core::trace::Trace;
+use frontend::window::Hotkey;
+use minifb::Key;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+const FAST_FORWARD: f64 = 4.0; // speed multiplier while Tab is held
 
 fn main() {
-    let rom_path = env::args().nth(1).unwrap();
-    let mut debug = false;
-    let mut interactive = false;
-    if let Some(_debug) = env::args().nth(2) {
-        if _debug == "-d" {
-            debug = true;
-        }
-    }
-    if let Some(_interactive) = env::args().nth(3) {
-        if _interactive == "-i" {
-            interactive = true;
+    let argv: Vec<String> = env::args().skip(1).collect();
+    // subcommands that never open a window
+    let tool = match argv.first().map(String::as_str) {
+        Some("disasm") => Some(frontend::tools::disasm(&argv[1..])),
+        Some("asm") => Some(frontend::tools::asm(&argv[1..])),
+        _ => None
+    };
+    if let Some(result) = tool {
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            process::exit(1);
         }
+        return;
     }
 
-    let mut cpu = Cpu::new(rom_path, debug, interactive);
-    cpu.load_font();
-    cpu.load_rom();
-    cpu.init();
-
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-
-    let mut window = match Window::new("rip8", WIDTH, HEIGHT,
-                                       WindowOptions {
-                                           resize: false,
-                                           scale: Scale::X8,
-                                           ..WindowOptions::default()
-                                       }) {
-        Ok(win) => win,
+    let args = match frontend::args::Args::parse() {
+        Ok(args) => args,
         Err(err) => {
-            println!("Unable to create window {}", err);
-            return;
+            eprintln!("{}", err);
+            process::exit(1);
         }
     };
 
-    let mut task_time = 0;
-    let sleep_time: i32 = 1000/500;
-
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        task_time = time::now().tm_sec * 1000;
-        {
-            // todo: redo this.
-            if window.is_key_down(Key::Key1) {
-                cpu.keyboard.set(0x01);
-            } else {
-                cpu.keyboard.unset(0x01);
-            }
-            if window.is_key_down(Key::Key2) {
-                cpu.keyboard.set(0x02);
-            } else {
-                cpu.keyboard.unset(0x02);
-            }
-            if window.is_key_down(Key::Key3) {
-                cpu.keyboard.set(0x03);
-            } else {
-                cpu.keyboard.unset(0x03);
-            }
-            if window.is_key_down(Key::Key4) {
-                cpu.keyboard.set(0x0C);
-            } else {
-                cpu.keyboard.unset(0x0C);
-            }
-            if window.is_key_down(Key::Q) {
-                cpu.keyboard.set(0x04);
-            } else {
-                cpu.keyboard.unset(0x04);
+    // the editor picks the ROM and its symbol file when debugging over DAP
+    let mut dap = if args.dap {
+        let server = match args.dap_address {
+            Some(ref address) => {
+                eprintln!("Waiting for a debug adapter client on {}", address);
+                DapServer::listen(address).map_err(|err| format!("Unable to listen on {}: {}", address, err))
+            },
+            None => Ok(DapServer::stdio())
+        };
+        match server.and_then(|mut server| server.wait_for_launch().map(|launch| (server, launch))) {
+            Ok(launched) => Some(launched),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
             }
-            if window.is_key_down(Key::W) {
-                cpu.keyboard.set(0x05);
-            } else {
-                cpu.keyboard.unset(0x05);
-            }
-            if window.is_key_down(Key::E) {
-                cpu.keyboard.set(0x06);
-            } else {
-                cpu.keyboard.unset(0x06);
-            }
-            if window.is_key_down(Key::R) {
-                cpu.keyboard.set(0x0D);
-            } else {
-                cpu.keyboard.unset(0x0D);
-            }
-            if window.is_key_down(Key::A) {
-                cpu.keyboard.set(0x07);
-            } else {
-                cpu.keyboard.unset(0x07);
-            }
-            if window.is_key_down(Key::S) {
-                cpu.keyboard.set(0x08);
-            } else {
-                cpu.keyboard.unset(0x08);
-            }
-            if window.is_key_down(Key::D) {
-                cpu.keyboard.set(0x09);
-            } else {
-                cpu.keyboard.unset(0x09);
-            }
-            if window.is_key_down(Key::F) {
-                cpu.keyboard.set(0x0E);
-            } else {
-                cpu.keyboard.unset(0x0E);
-            }
-            if window.is_key_down(Key::Y) || window.is_key_down(Key::Z) {
-                cpu.keyboard.set(0x0A);
-            } else {
-                cpu.keyboard.unset(0x0A);
-            }
-            if window.is_key_down(Key::X) {
-                cpu.keyboard.set(0x00);
-            } else {
-                cpu.keyboard.unset(0x00);
+        }
+    } else {
+        None
+    };
+    let rom_path = match dap {
+        Some((_, ref launch)) if !launch.program.is_empty() => launch.program.clone(),
+        _ => args.rom_path.clone()
+    };
+
+    let rom = Rom::new(rom_path.clone());
+    if let Some((ref mut server, ref launch)) = dap {
+        let symbols = load_symbols(launch.symbols.as_ref().or(args.symbols.as_ref()), &rom_path);
+        server.launched(rom.as_ref().map(|_| ()).map_err(|err| err.to_string()), symbols);
+    }
+    let rom = match rom {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let movie = args.play.as_ref().map(|path| {
+        match fs::read(path).map_err(|err| err.to_string())
+            .and_then(|bytes| Movie::from_bytes(&bytes, &rom.rom).map_err(|err| err.to_string())) {
+            Ok(movie) => movie,
+            Err(err) => {
+                eprintln!("Unable to load movie from {}: {}", path, err);
+                process::exit(1);
             }
-            if window.is_key_down(Key::C) {
-                cpu.keyboard.set(0x0B);
-            } else {
-                cpu.keyboard.unset(0x0B);
+        }
+    });
+    let rom_hash = state::rom_hash(&rom.rom);
+    let keymap = match load_keymap(args.keymap.as_ref(), rom_hash) {
+        Ok(keymap) => keymap,
+        Err(err) => {
+            eprintln!("Unable to load keymap: {}", err);
+            process::exit(1);
+        }
+    };
+    let mut cpu = Cpu::new(rom, args.quirks, args.debug, args.interactive);
+    cpu.set_instructions_per_frame(args.ipf);
+    cpu.set_rewind_frames(args.rewind_frames);
+    if let Some(seed) = args.seed {
+        cpu.reseed(seed);
+    }
+    cpu.load_font();
+    if let Err(err) = cpu.load_rom() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    if let Some(movie) = movie {
+        if let Err(err) = cpu.play_movie(movie) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+    if args.record.is_some() {
+        cpu.record_movie();
+    }
+    if args.coverage.is_some() || args.coverage_png.is_some() {
+        cpu.track_coverage();
+    }
+    if args.profile || args.profile_json.is_some() {
+        cpu.set_profile(Profile::new());
+    }
+    if let Some(ref path) = args.trace {
+        match File::create(path) {
+            Ok(file) => cpu.set_trace(Trace::new(Box::new(BufWriter::new(file)), args.trace_filter.clone())),
+            Err(err) => {
+                eprintln!("Unable to write trace to {}: {}", path, err);
+                process::exit(1);
             }
-            if window.is_key_down(Key::V) {
-                cpu.keyboard.set(0x0F);
-            } else {
-                cpu.keyboard.unset(0x0F);
+        }
+    }
+    if args.debug {
+        eprintln!("RNG seed: {}", cpu.seed());
+        eprintln!("ROM hash: {:016x}", rom_hash);
+    }
+    if let Some(ref address) = args.gdb {
+        eprintln!("Waiting for gdb on {}", address);
+        match GdbStub::listen(address) {
+            Ok(stub) => cpu.attach(Box::new(stub)),
+            Err(err) => {
+                eprintln!("Unable to listen for gdb on {}: {}", address, err);
+                process::exit(1);
             }
         }
+    }
+    if let Some((server, _)) = dap {
+        cpu.attach(Box::new(server));
+    }
+    let rpl_path = rpl::path_for(&rom_path);
+    match rpl::load(&rpl_path) {
+        Ok(flags) => cpu.set_rpl_flags(flags),
+        Err(err) => eprintln!("Unable to load RPL flags from {}: {}", rpl_path.display(), err)
+    }
+    let mut desktop = match frontend::window::Desktop::new() {
+        Ok(desktop) => desktop,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    if let Err(err) = desktop.set_keymap(&keymap) {
+        eprintln!("Unable to load keymap: {}", err);
+        process::exit(1);
+    }
 
-        cpu.tick();
-        let screen = cpu.screen.screen;
+    let mut clock = Clock::new();
+    clock.set_uncapped(args.benchmark);
+    let mut exit_code = 0;
 
-        for y in 0..32 {
-            for x in 0..64 {
-                buffer[y * 64 + x] = (screen[x][y] as u32) * 0xFFFFFF;
+    while desktop.is_open() && !desktop.is_key_down(Key::Escape) && !cpu.exited() {
+        if desktop.is_key_down(Key::Tab) {
+            clock.set_speed(args.speed * FAST_FORWARD);
+        } else {
+            clock.set_speed(args.speed);
+        }
+        match desktop.hotkey() {
+            Some(Hotkey::SaveState(slot)) => {
+                let path = state::path_for(&rom_path, slot);
+                match fs::write(&path, cpu.save_state()) {
+                    Ok(()) => eprintln!("Saved state to {}", path.display()),
+                    Err(err) => eprintln!("Unable to save state to {}: {}", path.display(), err)
+                }
+            },
+            Some(Hotkey::LoadState(slot)) => {
+                let path = state::path_for(&rom_path, slot);
+                match fs::read(&path).map_err(|err| err.to_string())
+                    .and_then(|bytes| cpu.load_state(&bytes).map_err(|err| err.to_string())) {
+                    Ok(()) => eprintln!("Loaded state from {}", path.display()),
+                    Err(err) => eprintln!("Unable to load state from {}: {}", path.display(), err)
+                }
+            },
+            None => {}
+        }
+
+        // hold backspace to go back in time, one frame per frame
+        if desktop.is_key_down(Key::Backspace) {
+            if let Err(err) = cpu.rewind() {
+                eprintln!("Unable to rewind: {}", err);
             }
+            desktop.present(cpu.screen());
+            desktop.play(&Tone::default());
+            clock.frame_done(0);
+            continue;
         }
 
-        window.update_with_buffer(&buffer).unwrap();
+        let cycles = cpu.cycles();
+        let movie_was_playing = args.play.is_some() && !cpu.movie_finished();
+        if let Err(err) = cpu.run_frame_with(&mut desktop) {
+            eprintln!("\nCPU stopped: {}", err);
+            eprintln!("Register dump: {:#?}", cpu.registers());
+            exit_code = 1;
+            break;
+        }
+        if movie_was_playing && cpu.movie_finished() {
+            eprintln!("Movie finished after {} frames", cpu.frames());
+        }
+        clock.frame_done(cpu.cycles() - cycles);
+    }
 
-        task_time = (time::now().tm_sec * 1000) - task_time;
-        if sleep_time - task_time > 0 {
-            thread::sleep_ms((sleep_time - task_time) as u32);
+    if let Some(ref path) = args.record {
+        let movie = cpu.movie().expect("recording").to_bytes();
+        match fs::write(path, movie) {
+            Ok(()) => eprintln!("Saved movie to {}", path),
+            Err(err) => eprintln!("Unable to save movie to {}: {}", path, err)
+        }
+    }
+
+    if args.benchmark {
+        eprintln!("{:.1} frames/s, {:.0} instructions/s", clock.frames_per_second(), clock.instructions_per_second());
+    }
+
+    cpu.detach(exit_code);
+    if let Some(coverage) = cpu.ram().coverage() {
+        let save = |path: &String, bytes: Vec<u8>| match fs::write(path, bytes) {
+            Ok(()) => eprintln!("Saved coverage to {}", path),
+            Err(err) => eprintln!("Unable to save coverage to {}: {}", path, err)
+        };
+        if let Some(ref path) = args.coverage {
+            save(path, coverage.map().into_bytes());
+        }
+        if let Some(ref path) = args.coverage_png {
+            save(path, coverage.overlay(&cpu.ram().ram));
+        }
+    }
+    if let Some(mut profile) = cpu.take_profile() {
+        profile.finish();
+        let symbols = load_symbols(args.symbols.as_ref(), &rom_path);
+        match args.profile_json {
+            Some(ref path) => match fs::write(path, format!("{}\n", profile.to_json(&symbols))) {
+                Ok(()) => eprintln!("Saved profile to {}", path),
+                Err(err) => eprintln!("Unable to save profile to {}: {}", path, err)
+            },
+            // stdout belongs to the debug adapter protocol when it runs over stdio
+            None if args.dap && args.dap_address.is_none() => eprint!("{}", profile.report(&symbols)),
+            None => print!("{}", profile.report(&symbols))
         }
     }
-}
\ No newline at end of file
+    if let Some(trace) = cpu.take_trace() {
+        if let Err(err) = trace.finish() {
+            eprintln!("Unable to write trace to {}: {}", args.trace.as_ref().expect("tracing"), err);
+        }
+    }
+    if let Err(err) = rpl::save(&rpl_path, cpu.rpl_flags()) {
+        eprintln!("Unable to save RPL flags to {}: {}", rpl_path.display(), err);
+    }
+    process::exit(exit_code);
+}
+
+// Symbols from the assembler, next to the ROM unless given. Debuggers and the profiler get by without.
+fn load_symbols(path: Option<&String>, rom_path: &str) -> Symbols {
+    let path = path.map_or_else(|| Path::new(rom_path).with_extension("sym"), PathBuf::from);
+    fs::read_to_string(path).map(|text| Symbols::parse(&text)).unwrap_or_default()
+}
+
+// A preset name, a config file, or the config file in the user's config directory if there is one
+fn load_keymap(arg: Option<&String>, rom_hash: u64) -> Result<Keymap, String> {
+    if let Some(keymap) = arg.and_then(|name| Keymap::preset(name)) {
+        return Ok(keymap);
+    }
+    let path = match arg {
+        Some(path) => PathBuf::from(path),
+        None => match env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config"))) {
+            Some(config) if config.join("rip8/keymap.toml").exists() => config.join("rip8/keymap.toml"),
+            _ => return Ok(Keymap::default())
+        }
+    };
+    let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Keymap::parse(&text, rom_hash).map_err(|err| format!("{}: {}", path.display(), err))
+}